@@ -0,0 +1,250 @@
+use crate::configuration::BatteryLevel;
+use once_cell::sync::Lazy;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+const HISTORY_LENGTH: usize = 256;
+
+/// Latest status of every connected controller, keyed by serial number.
+static BATTERY_STATUS: Lazy<Mutex<BTreeMap<String, BatteryStatus>>> =
+    Lazy::new(|| Mutex::new(BTreeMap::new()));
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BatterySample {
+    pub at: SystemTime,
+    pub level: BatteryLevel,
+    pub is_charging: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct BatteryStatus {
+    pub serial_number: String,
+    pub level: BatteryLevel,
+    pub is_charging: bool,
+    pub estimated_empty_at: Option<SystemTime>,
+    pub history: Vec<BatterySample>,
+}
+
+impl BatteryStatus {
+    pub fn estimated_remaining(&self) -> Option<Duration> {
+        self.estimated_empty_at?
+            .duration_since(SystemTime::now())
+            .ok()
+    }
+}
+
+pub fn battery_status() -> Vec<BatteryStatus> {
+    BATTERY_STATUS.lock().unwrap().values().cloned().collect()
+}
+
+/// Records the battery of one controller. Samples are only kept when the level or
+/// charging state changes.
+pub struct BatteryMonitor {
+    serial_number: String,
+    history: VecDeque<BatterySample>,
+    alerted: Option<BatteryLevel>,
+}
+
+impl BatteryMonitor {
+    pub fn new(serial_number: &str) -> Self {
+        BatteryMonitor {
+            serial_number: serial_number.to_string(),
+            history: VecDeque::new(),
+            alerted: None,
+        }
+    }
+
+    /// Returns the level to warn about when the battery has just dropped to Low or Critical.
+    pub fn update(&mut self, level: BatteryLevel, is_charging: bool) -> Option<BatteryLevel> {
+        let changed = match self.history.back() {
+            Some(last) => last.level != level || last.is_charging != is_charging,
+            None => true,
+        };
+        if !changed {
+            return None;
+        }
+
+        if self.history.len() == HISTORY_LENGTH {
+            self.history.pop_front();
+        }
+        self.history.push_back(BatterySample {
+            at: SystemTime::now(),
+            level,
+            is_charging,
+        });
+        self.publish();
+
+        if is_charging || level > BatteryLevel::Low {
+            self.alerted = None;
+            return None;
+        }
+
+        let alert = level.max(BatteryLevel::Critical);
+        let is_new = match self.alerted {
+            Some(alerted) => alert < alerted,
+            None => true,
+        };
+        if is_new {
+            self.alerted = Some(alert);
+            return Some(alert);
+        }
+        None
+    }
+
+    /// Estimates from the time the current discharge took per level.
+    pub fn estimate_empty_at(&self) -> Option<SystemTime> {
+        let last = self.history.back()?;
+        if last.is_charging {
+            return None;
+        }
+
+        let discharging = self
+            .history
+            .iter()
+            .rev()
+            .take_while(|sample| !sample.is_charging)
+            .collect::<Vec<_>>();
+        // The first sample of a discharge is when we started watching, not a level drop.
+        let first_drop = discharging.iter().rev().nth(1)?;
+        let dropped_levels = (first_drop.level as u32)
+            .checked_sub(last.level as u32)
+            .filter(|&levels| levels > 0)?;
+
+        let discharge_time = last.at.duration_since(first_drop.at).ok()?;
+        let per_level = discharge_time / dropped_levels;
+        Some(last.at + per_level * last.level as u32)
+    }
+
+    fn publish(&self) {
+        let last = match self.history.back() {
+            Some(last) => last,
+            None => return,
+        };
+
+        let status = BatteryStatus {
+            serial_number: self.serial_number.clone(),
+            level: last.level,
+            is_charging: last.is_charging,
+            estimated_empty_at: self.estimate_empty_at(),
+            history: self.history.iter().copied().collect(),
+        };
+        BATTERY_STATUS
+            .lock()
+            .unwrap()
+            .insert(self.serial_number.clone(), status);
+    }
+}
+
+impl Drop for BatteryMonitor {
+    fn drop(&mut self) {
+        BATTERY_STATUS.lock().unwrap().remove(&self.serial_number);
+    }
+}
+
+pub fn format_battery_status(status: &BatteryStatus) -> String {
+    let mut text = format!("{:?}", status.level);
+    let since = status.history.last().and_then(|s| s.at.elapsed().ok());
+    if let Some(since) = since {
+        text.push_str(&format!(" for {}", format_duration(since)));
+    }
+
+    if status.is_charging {
+        text.push_str(" (charging)");
+    } else if let Some(remaining) = status.estimated_remaining() {
+        text.push_str(&format!(" ({} left)", format_duration(remaining)));
+    }
+    text
+}
+
+fn format_duration(duration: Duration) -> String {
+    let minutes = duration.as_secs() / 60;
+    format!("{}h {:02}m", minutes / 60, minutes % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(hours: u64, level: BatteryLevel, is_charging: bool) -> BatterySample {
+        BatterySample {
+            at: SystemTime::UNIX_EPOCH + Duration::from_secs(hours * 3600),
+            level,
+            is_charging,
+        }
+    }
+
+    #[test]
+    fn alerts_are_raised_once_per_drop() {
+        let mut monitor = BatteryMonitor::new("battery-alerts");
+        assert_eq!(monitor.update(BatteryLevel::Medium, false), None);
+        assert_eq!(
+            monitor.update(BatteryLevel::Low, false),
+            Some(BatteryLevel::Low)
+        );
+        assert_eq!(monitor.update(BatteryLevel::Low, false), None);
+        assert_eq!(
+            monitor.update(BatteryLevel::Critical, false),
+            Some(BatteryLevel::Critical)
+        );
+        // Empty is warned about as Critical, which was already.
+        assert_eq!(monitor.update(BatteryLevel::Empty, false), None);
+        // A reading that bounces back up isn't a new drop.
+        assert_eq!(monitor.update(BatteryLevel::Low, false), None);
+
+        assert_eq!(monitor.update(BatteryLevel::Low, true), None);
+        assert_eq!(
+            monitor.update(BatteryLevel::Low, false),
+            Some(BatteryLevel::Low)
+        );
+    }
+
+    #[test]
+    fn history_keeps_the_latest_changes() {
+        let mut monitor = BatteryMonitor::new("battery-history");
+        for i in 0..HISTORY_LENGTH + 10 {
+            monitor.update(BatteryLevel::Full, i % 2 == 0);
+        }
+        assert_eq!(monitor.history.len(), HISTORY_LENGTH);
+        assert!(!monitor.history.back().unwrap().is_charging);
+
+        let status = battery_status()
+            .into_iter()
+            .find(|status| status.serial_number == "battery-history")
+            .unwrap();
+        assert_eq!(status.history.len(), HISTORY_LENGTH);
+    }
+
+    #[test]
+    fn empty_is_estimated_from_the_current_discharge() {
+        let mut monitor = BatteryMonitor::new("battery-estimate");
+        monitor.history = vec![sample(0, BatteryLevel::Full, false)].into();
+        assert_eq!(monitor.estimate_empty_at(), None);
+
+        // Nothing dropped since charging stopped.
+        monitor.history = vec![
+            sample(0, BatteryLevel::Low, false),
+            sample(1, BatteryLevel::Medium, true),
+            sample(2, BatteryLevel::Medium, false),
+        ]
+        .into();
+        assert_eq!(monitor.estimate_empty_at(), None);
+
+        monitor
+            .history
+            .push_back(sample(3, BatteryLevel::Medium, true));
+        assert_eq!(monitor.estimate_empty_at(), None);
+
+        // Full to Medium at hour 1 is where the estimate starts, Medium to Low took 2 hours.
+        monitor.history = vec![
+            sample(0, BatteryLevel::Full, false),
+            sample(1, BatteryLevel::Medium, false),
+            sample(3, BatteryLevel::Low, false),
+        ]
+        .into();
+        assert_eq!(
+            monitor.estimate_empty_at(),
+            Some(sample(7, BatteryLevel::Empty, false).at)
+        );
+    }
+}
@@ -0,0 +1,478 @@
+use crate::config_format::ConfigFormat;
+use crate::configuration::{create_default, Config, HapticSegment, Profile};
+use crate::haptics::resolve_preset;
+use crate::migration::{migrate, CONFIG_VERSION};
+use crate::validation::validate_config;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::fs::{read_to_string, write};
+use std::path::Path;
+
+/// One profile in a file of its own, to share it. It has the haptic presets it plays,
+/// so it works the same in settings that don't have them.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ProfileBundle {
+    /// Format of the profile, like `version` of the config.
+    pub version: u32,
+    /// joyshop the bundle was exported from.
+    pub joyshop_version: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    /// App the bindings are for.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub app: Option<String>,
+    #[serde(default)]
+    pub haptic_presets: BTreeMap<String, Vec<HapticSegment>>,
+    pub profile: Profile,
+}
+
+impl ProfileBundle {
+    /// The profile called `name` in `config`, with its mirror already applied.
+    pub fn new(
+        config: &Config,
+        name: &str,
+        author: Option<String>,
+        app: Option<String>,
+    ) -> Result<ProfileBundle, String> {
+        let index = config
+            .profile_index(name)
+            .ok_or_else(|| format!("unknown profile: {}", name))?;
+        let mut profile = config.profile(index).clone();
+        profile.mirror = None;
+
+        let mut haptic_presets = BTreeMap::new();
+        for preset in played_presets(&serde_json::to_value(&profile).unwrap()) {
+            if let Some(segments) = resolve_preset(config, &preset) {
+                haptic_presets.insert(preset, segments);
+            }
+        }
+        Ok(ProfileBundle {
+            version: CONFIG_VERSION,
+            joyshop_version: env!("CARGO_PKG_VERSION").into(),
+            author,
+            app,
+            haptic_presets,
+            profile,
+        })
+    }
+
+    pub fn read(path: &Path) -> Result<ProfileBundle, String> {
+        let format = ConfigFormat::from_path(path)?;
+        let text = read_to_string(path).map_err(|e| format!("couldn't load file error: {}", e))?;
+        let mut value = format.parse(&text).map_err(|(line, message)| match line {
+            Some(line) => format!("{} (line {})", message, line),
+            None => message,
+        })?;
+        migrate_bundle(&mut value)?;
+        let bundle: ProfileBundle = serde_json::from_value(value)
+            .map_err(|e| format!("{} isn't a profile bundle error: {}", path.display(), e))?;
+        if bundle.version > CONFIG_VERSION {
+            return Err(format!(
+                "the profile is from a newer joyshop ({}), update joyshop to import it",
+                bundle.joyshop_version
+            ));
+        }
+        Ok(bundle)
+    }
+
+    /// Writes the bundle in the format of `path`.
+    pub fn write(&self, path: &Path) -> Result<(), String> {
+        let format = ConfigFormat::from_path(path)?;
+        let text = format.write(&serde_json::to_value(self).unwrap())?;
+        write(path, text).map_err(|e| format!("couldn't write {} error: {}", path.display(), e))
+    }
+
+    /// One line about the bundle, like `Inking by Sam for Krita (joyshop 0.1.1)`.
+    pub fn describe(&self) -> String {
+        let mut description = self.profile.name.clone();
+        if let Some(author) = &self.author {
+            description += &format!(" by {}", author);
+        }
+        if let Some(app) = &self.app {
+            description += &format!(" for {}", app);
+        }
+        description + &format!(" (joyshop {})", self.joyshop_version)
+    }
+}
+
+/// What to do when the config already has a profile with the name of the imported one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Conflict {
+    Refuse,
+    Replace,
+}
+
+/// Profiles of an older joyshop are migrated like a config, whose base profile has the same
+/// settings, with the haptic presets of the bundle.
+fn migrate_bundle(bundle: &mut Value) -> Result<(), String> {
+    let version = match bundle.get("version").and_then(|version| version.as_u64()) {
+        Some(version) if version < CONFIG_VERSION as u64 => version,
+        // Newer bundles are refused once they're read.
+        _ => return Ok(()),
+    };
+    let mut config = match bundle.get("profile") {
+        Some(Value::Object(profile)) => profile.clone(),
+        _ => return Ok(()),
+    };
+    config.insert("version".into(), version.into());
+    if let Some(presets) = bundle.get("haptic_presets") {
+        config.insert("haptic_presets".into(), presets.clone());
+    }
+
+    let mut profile = Value::Object(config);
+    migrate(&mut profile)?;
+    if let Some(profile) = profile.as_object_mut() {
+        profile.remove("version");
+        if let Some(presets) = profile.remove("haptic_presets") {
+            bundle["haptic_presets"] = presets;
+        }
+    }
+    bundle["profile"] = profile;
+    bundle["version"] = CONFIG_VERSION.into();
+    Ok(())
+}
+
+/// Adds the profile of `bundle` to the config file, as `name` when given.
+/// Haptic presets the config has with other patterns are imported with the profile name added.
+/// Returns the name of the imported profile.
+///
+/// Names are checked against the profiles of the file itself, not the ones it `extends`, and the
+/// file is only written once the profile can be added.
+pub fn import_bundle(
+    config_path: &Path,
+    bundle: ProfileBundle,
+    name: Option<String>,
+    conflict: Conflict,
+) -> Result<String, String> {
+    if !config_path.exists() {
+        return Err(format!(
+            "there's no config file at {}, run joyshop once to write the default one",
+            config_path.display()
+        ));
+    }
+    let format = ConfigFormat::from_path(config_path)?;
+    let text =
+        read_to_string(config_path).map_err(|e| format!("couldn't load file error: {}", e))?;
+    let mut value = format
+        .parse(&text)
+        .map_err(|(_, message)| format!("fix {} first: {}", config_path.display(), message))?;
+    let file = value
+        .as_object_mut()
+        .ok_or_else(|| format!("{} must be an object", config_path.display()))?;
+
+    let name = name.unwrap_or_else(|| bundle.profile.name.clone());
+    let base_name = file
+        .get("name")
+        .and_then(|n| n.as_str())
+        .unwrap_or("Default");
+    let exists = match file.get("profiles") {
+        Some(Value::Array(profiles)) => profiles
+            .iter()
+            .any(|profile| profile.get("name").and_then(|n| n.as_str()) == Some(name.as_str())),
+        Some(_) => return Err("profiles must be a list".into()),
+        None => false,
+    };
+    if base_name == name {
+        return Err(format!(
+            "{} is the base profile, import the profile with --as <name>",
+            name
+        ));
+    }
+    if exists && conflict == Conflict::Refuse {
+        return Err(format!(
+            "there's already a profile called {}, import it with --as <name> or --replace",
+            name
+        ));
+    }
+
+    // Haptic presets are the ones the profile would play, so what the file extends counts.
+    let (resolved, _) = validate_config(&text, format, Some(config_path));
+    let config = resolved
+        .and_then(|resolved| serde_json::from_value::<Config>(resolved).ok())
+        .unwrap_or_else(create_default);
+
+    let mut profile = serde_json::to_value(&bundle.profile).unwrap();
+    profile["name"] = name.clone().into();
+    let mut haptic_presets = Map::new();
+    for (preset, segments) in bundle.haptic_presets {
+        match resolve_preset(&config, &preset) {
+            Some(existing) if existing == segments => {}
+            Some(_) => {
+                let renamed = format!("{} ({})", preset, name);
+                rename_preset(&mut profile, &preset, &renamed);
+                haptic_presets.insert(renamed, serde_json::to_value(segments).unwrap());
+            }
+            None => {
+                haptic_presets.insert(preset, serde_json::to_value(segments).unwrap());
+            }
+        }
+    }
+
+    let mut additions = Map::new();
+    if !haptic_presets.is_empty() {
+        match file
+            .entry("haptic_presets")
+            .or_insert_with(|| Value::Object(Map::new()))
+        {
+            Value::Object(presets) => presets.extend(haptic_presets.clone()),
+            _ => return Err("haptic_presets must be an object".into()),
+        }
+        additions.insert("haptic_presets".into(), Value::Object(haptic_presets));
+    }
+    if let Some(Value::Array(profiles)) = file.get_mut("profiles") {
+        profiles.retain(|existing| existing.get("name") != Some(&profile["name"]));
+    }
+    match file
+        .entry("profiles")
+        .or_insert_with(|| Value::Array(Vec::new()))
+    {
+        Value::Array(profiles) => profiles.push(profile.clone()),
+        _ => return Err("profiles must be a list".into()),
+    }
+    additions.insert("profiles".into(), json!([profile]));
+
+    // Replacing a profile changes what's already written, so then the whole file is.
+    let appended = if exists {
+        None
+    } else {
+        append(&text, format, &additions, &value)
+    };
+    let text = match appended {
+        Some(text) => text,
+        None => {
+            if format != ConfigFormat::Json {
+                println!(
+                    "[Config] {} is written anew, without its comments",
+                    config_path.display()
+                );
+            }
+            format.write(&value)?
+        }
+    };
+    write(config_path, text)
+        .map_err(|e| format!("couldn't write {} error: {}", config_path.display(), e))?;
+    Ok(name)
+}
+
+/// `text` with `additions` written after what it has, so its comments and layout are kept.
+/// `None` when that doesn't read back as `expected`, like in JSON, or when the file has the
+/// profiles as an inline list.
+fn append(
+    text: &str,
+    format: ConfigFormat,
+    additions: &Map<String, Value>,
+    expected: &Value,
+) -> Option<String> {
+    let appended = match format {
+        ConfigFormat::Json => return None,
+        ConfigFormat::Toml => {
+            let additions = format.write(&Value::Object(additions.clone())).ok()?;
+            format!("{}\n\n{}", text.trim_end(), additions)
+        }
+        ConfigFormat::Yaml => {
+            let mut text = text.to_string();
+            for (key, entries) in additions {
+                text = append_yaml(&text, key, entries)?;
+            }
+            text
+        }
+    };
+    let written = |value: &Value| format.write(value).ok();
+    let is_expected = written(&format.parse(&appended).ok()?)? == written(expected)?;
+    is_expected.then_some(appended)
+}
+
+/// Adds `entries`, a list or a map, to the end of the block of the top level `key`,
+/// or adds the block to the end of the file when it has none.
+fn append_yaml(text: &str, key: &str, entries: &Value) -> Option<String> {
+    let lines = text.lines().collect::<Vec<_>>();
+    let start = match lines
+        .iter()
+        .position(|line| line.starts_with(&format!("{}:", key)))
+    {
+        Some(start) => start,
+        None => {
+            let block = ConfigFormat::Yaml.write(&json!({ key: entries })).ok()?;
+            return Some(format!("{}\n{}", text.trim_end(), block));
+        }
+    };
+    // Inline lists and maps are left for the caller to write anew.
+    if lines[start].trim_end() != format!("{}:", key) {
+        return None;
+    }
+
+    let is_in_block = |line: &str| {
+        line.is_empty() || line.starts_with(' ') || line.starts_with('#') || line.starts_with('-')
+    };
+    let mut end = (start + 1..lines.len())
+        .find(|&index| !is_in_block(lines[index]))
+        .unwrap_or(lines.len());
+    // Blank lines and comments after the block stay after it.
+    while end > start + 1 && (lines[end - 1].trim().is_empty() || lines[end - 1].starts_with('#')) {
+        end -= 1;
+    }
+    let indent = lines[start + 1..end]
+        .iter()
+        .find(|line| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .map_or(2, |line| line.len() - line.trim_start().len());
+
+    let entries = ConfigFormat::Yaml.write(entries).ok()?;
+    let entries = entries
+        .lines()
+        .map(|line| format!("{}{}", " ".repeat(indent), line));
+    let mut text = lines[..end]
+        .iter()
+        .map(|line| line.to_string())
+        .chain(entries)
+        .chain(lines[end..].iter().map(|line| line.to_string()))
+        .collect::<Vec<_>>()
+        .join("\n");
+    text.push('\n');
+    Some(text)
+}
+
+/// Names of the haptic presets a profile plays.
+fn played_presets(value: &Value) -> Vec<String> {
+    let mut names = Vec::new();
+    match value {
+        Value::Object(object) => {
+            if let Some(Value::String(name)) = object.get("Preset") {
+                names.push(name.clone());
+            }
+            names.extend(object.values().flat_map(played_presets));
+        }
+        Value::Array(array) => names.extend(array.iter().flat_map(played_presets)),
+        _ => {}
+    }
+    names.sort();
+    names.dedup();
+    names
+}
+
+fn rename_preset(value: &mut Value, from: &str, to: &str) {
+    match value {
+        Value::Object(object) => {
+            if let Some(name) = object.get_mut("Preset").filter(|name| *name == from) {
+                *name = to.into();
+            }
+            object
+                .values_mut()
+                .for_each(|value| rename_preset(value, from, to));
+        }
+        Value::Array(array) => array
+            .iter_mut()
+            .for_each(|value| rename_preset(value, from, to)),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn temp_file(name: &str, text: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("joyshop-{}-{}", std::process::id(), name));
+        write(&path, text).unwrap();
+        path
+    }
+
+    /// The default base profile as `Inking`, which plays `file_saved`.
+    fn bundle() -> ProfileBundle {
+        let mut config = create_default();
+        config.profile.name = "Inking".into();
+        ProfileBundle::new(&config, "Inking", None, None).unwrap()
+    }
+
+    fn import(path: &Path, name: Option<&str>, conflict: Conflict) -> Result<String, String> {
+        import_bundle(path, bundle(), name.map(|name| name.to_string()), conflict)
+    }
+
+    #[test]
+    fn toml_keeps_its_comments_and_other_patterns_are_renamed() {
+        let text = "# mine\nshow_tooltip = false\n\n[[haptic_presets.file_saved]]\nfrequency = 100.0\namplitude = 0.5\nduration_ms = 40\nmotor = \"Both\"\n";
+        let path = temp_file("import.toml", text);
+        let imported = import(&path, None, Conflict::Refuse);
+        let written = read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(imported, Ok("Inking".to_string()));
+        assert!(written.starts_with(text), "{}", written);
+        let value = ConfigFormat::Toml.parse(&written).unwrap();
+        assert!(value["haptic_presets"]["file_saved (Inking)"].is_array());
+        assert_eq!(value["profiles"][0]["name"], "Inking");
+        assert_eq!(
+            played_presets(&value["profiles"][0]),
+            ["file_saved (Inking)"]
+        );
+    }
+
+    #[test]
+    fn yaml_profiles_are_added_to_their_list() {
+        let text = "# mine\nprofiles:\n  # first\n  - name: Paint\n    zl: None\n\n# last\n";
+        let path = temp_file("import.yaml", text);
+        let imported = import(&path, Some("Ink"), Conflict::Refuse);
+        let written = read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(imported, Ok("Ink".to_string()));
+        assert!(written.starts_with(
+            "# mine\nprofiles:\n  # first\n  - name: Paint\n    zl: None\n  - name: Ink\n"
+        ));
+        assert!(written.ends_with("\n\n# last\n"));
+        let value = ConfigFormat::Yaml.parse(&written).unwrap();
+        assert_eq!(value["profiles"].as_array().unwrap().len(), 2);
+        assert!(value.get("haptic_presets").is_none());
+    }
+
+    #[test]
+    fn refused_imports_leave_the_file_alone() {
+        let text = "{\"name\": \"Base\", \"profiles\": [{\"name\": \"Inking\"}]}";
+        let path = temp_file("refused.json", text);
+        let refused = import(&path, None, Conflict::Refuse);
+        let base = import(&path, Some("Base"), Conflict::Replace);
+        let written = read_to_string(&path).unwrap();
+        let replaced = import(&path, None, Conflict::Replace);
+        let value = ConfigFormat::Json
+            .parse(&read_to_string(&path).unwrap())
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(refused
+            .unwrap_err()
+            .starts_with("there's already a profile called Inking"));
+        assert!(base.unwrap_err().starts_with("Base is the base profile"));
+        assert_eq!(written, text);
+        assert_eq!(replaced, Ok("Inking".to_string()));
+        assert_eq!(value["profiles"].as_array().unwrap().len(), 1);
+        assert!(value["profiles"][0].get("zl").is_some());
+    }
+
+    #[test]
+    fn importing_needs_a_config_file() {
+        let path = std::env::temp_dir().join("joyshop-there-is-no-such-config.json");
+        let error = import(&path, None, Conflict::Refuse).unwrap_err();
+        assert!(error.starts_with("there's no config file at"), "{}", error);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn older_bundles_are_migrated_and_newer_ones_refused() {
+        let mut value = serde_json::to_value(bundle()).unwrap();
+        value["joyshop_version"] = "0.1.0".into();
+        value["version"] = 0.into();
+        let path = temp_file("old-bundle.json", &value.to_string());
+        let old = ProfileBundle::read(&path);
+        value["version"] = (CONFIG_VERSION + 1).into();
+        write(&path, value.to_string()).unwrap();
+        let newer = ProfileBundle::read(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let old = old.unwrap();
+        assert_eq!(old.version, CONFIG_VERSION);
+        assert_eq!(old.profile.name, "Inking");
+        assert!(old.haptic_presets.contains_key("file_saved"));
+        assert!(newer.unwrap_err().contains("newer joyshop"));
+    }
+}
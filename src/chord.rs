@@ -0,0 +1,301 @@
+use crate::configuration::Key;
+
+/// Other names keys can be written with. Keys with a one character alias are written with it.
+const ALIASES: [(&str, Key); 45] = [
+    ("0", Key::Zero),
+    ("1", Key::One),
+    ("2", Key::Two),
+    ("3", Key::Three),
+    ("4", Key::Four),
+    ("5", Key::Five),
+    ("6", Key::Six),
+    ("7", Key::Seven),
+    ("8", Key::Eight),
+    ("9", Key::Nine),
+    ("[", Key::Oem4OpenSquareOrCurlyBracket),
+    ("]", Key::Oem6CloseSquareOrCurlyBracket),
+    (";", Key::Oem1SemiColonOrColon),
+    ("=", Key::OemPlus),
+    (",", Key::OemComma),
+    ("-", Key::OemMinus),
+    (".", Key::OemPeriod),
+    ("/", Key::Oem2SlashOrQuestion),
+    ("`", Key::Oem3BacktickOrTilda),
+    ("\\", Key::Oem5BackslashOrPipe),
+    ("'", Key::Oem7SingleOrDoubleQuote),
+    ("Plus", Key::OemPlus),
+    ("Minus", Key::OemMinus),
+    ("Comma", Key::OemComma),
+    ("Period", Key::OemPeriod),
+    ("Esc", Key::Escape),
+    ("Return", Key::Enter),
+    ("Del", Key::Delete),
+    ("Ins", Key::Insert),
+    ("PgUp", Key::PageUp),
+    ("PgDn", Key::PageDown),
+    ("Caps", Key::CapsLock),
+    ("PrtSc", Key::PrintScreen),
+    ("Menu", Key::Application),
+    ("ScrollLock", Key::Scroll),
+    ("Num0", Key::Numpad0),
+    ("Num1", Key::Numpad1),
+    ("Num2", Key::Numpad2),
+    ("Num3", Key::Numpad3),
+    ("Num4", Key::Numpad4),
+    ("Num5", Key::Numpad5),
+    ("Num6", Key::Numpad6),
+    ("Num7", Key::Numpad7),
+    ("Num8", Key::Numpad8),
+    ("Num9", Key::Numpad9),
+];
+
+/// Names of the keys that can be held with another one. The first name of a modifier is the one it's written as.
+/// `Ctrl`, `Alt` and `Shift` are either side, apps that tell them apart see the left one.
+const MODIFIERS: [(&str, Key); 17] = [
+    ("Ctrl", Key::Control),
+    ("Control", Key::Control),
+    ("Alt", Key::Alt),
+    ("Shift", Key::Shift),
+    ("Win", Key::LeftWindows),
+    ("Meta", Key::LeftWindows),
+    ("Super", Key::LeftWindows),
+    ("LCtrl", Key::LeftControl),
+    ("RCtrl", Key::RightControl),
+    ("LAlt", Key::LeftAlt),
+    ("RAlt", Key::RightAlt),
+    ("AltGr", Key::RightAlt),
+    ("LShift", Key::LeftShift),
+    ("RShift", Key::RightShift),
+    ("LWin", Key::LeftWindows),
+    ("RWin", Key::RightWindows),
+    ("RMeta", Key::RightWindows),
+];
+
+/// A key with the modifiers held with it, like `Ctrl+Shift+Z`.
+/// Modifiers are pressed in order and released in reverse.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Chord {
+    pub key: Key,
+    pub modifiers: Vec<Key>,
+}
+
+/// Every name `parse_key` reads, the names in `Key` first.
+pub fn key_names() -> Vec<String> {
+    Key::ALL
+        .iter()
+        .map(|key| key.to_string())
+        .chain(ALIASES.iter().map(|(alias, _)| alias.to_string()))
+        .collect()
+}
+
+/// Every name of a modifier in a chord.
+pub fn modifier_names() -> Vec<&'static str> {
+    MODIFIERS.iter().map(|(name, _)| *name).collect()
+}
+
+pub fn is_modifier(key: Key) -> bool {
+    MODIFIERS.iter().any(|(_, modifier)| *modifier == key)
+}
+
+/// Parses a key by its name in `Key` or an alias, ignoring case.
+pub fn parse_key(name: &str) -> Result<Key, String> {
+    let name = name.trim();
+    let found = Key::ALL
+        .iter()
+        .find(|key| key.to_string().eq_ignore_ascii_case(name))
+        .or_else(|| {
+            ALIASES
+                .iter()
+                .find(|(alias, _)| alias.eq_ignore_ascii_case(name))
+                .map(|(_, key)| key)
+        });
+    match found {
+        Some(key) => Ok(*key),
+        None => Err(unknown(name)),
+    }
+}
+
+/// Parses `+` separated modifiers followed by a key. `Ctrl++` is Ctrl with the `=`/`+` key.
+pub fn parse_chord(text: &str) -> Result<Chord, String> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Err("empty key combination".into());
+    }
+    let parts = match text.strip_suffix("++") {
+        Some(rest) => rest
+            .split('+')
+            .chain(std::iter::once("="))
+            .collect::<Vec<_>>(),
+        None if text == "+" => vec!["="],
+        None => text.split('+').collect::<Vec<_>>(),
+    };
+
+    let mut keys = Vec::new();
+    for part in parts {
+        let part = part.trim();
+        if part.is_empty() {
+            return Err(format!("missing key in `{}`", text));
+        }
+        let modifier = MODIFIERS
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(part))
+            .map(|(_, key)| *key);
+        keys.push((part, modifier.map_or_else(|| parse_key(part), Ok)?));
+    }
+
+    let (_, key) = keys.pop().unwrap();
+    let mut modifiers = Vec::new();
+    for (name, modifier) in keys {
+        if !is_modifier(modifier) {
+            return Err(format!(
+                "more than one key in `{}`, `{}` isn't a modifier",
+                text, name
+            ));
+        }
+        if !modifiers.contains(&modifier) {
+            modifiers.push(modifier);
+        }
+    }
+    Ok(Chord { key, modifiers })
+}
+
+/// Writes a chord the way `parse_chord` reads it.
+pub fn format_chord(chord: &Chord) -> String {
+    let mut parts = chord
+        .modifiers
+        .iter()
+        .map(|modifier| key_name(*modifier))
+        .collect::<Vec<_>>();
+    if chord.key != Key::None || parts.is_empty() {
+        parts.push(key_name(chord.key));
+    }
+    parts.join("+")
+}
+
+fn key_name(key: Key) -> String {
+    if let Some((name, _)) = MODIFIERS.iter().find(|(_, k)| *k == key) {
+        return name.to_string();
+    }
+    match ALIASES.iter().find(|(_, k)| *k == key) {
+        Some((alias, _)) if alias.len() == 1 => alias.to_string(),
+        _ => key.to_string(),
+    }
+}
+
+fn unknown(name: &str) -> String {
+    let names = key_names()
+        .into_iter()
+        .chain(MODIFIERS.iter().map(|(name, _)| name.to_string()));
+
+    let lowercase = name.to_lowercase();
+    let suggestion = names
+        .map(|candidate| (distance(&lowercase, &candidate.to_lowercase()), candidate))
+        .filter(|(distance, _)| *distance <= (name.chars().count() / 3).max(2))
+        .min_by_key(|(distance, _)| *distance);
+
+    match suggestion {
+        Some((_, candidate)) => format!("unknown key `{}` (did you mean `{}`?)", name, candidate),
+        None => format!("unknown key `{}` (see `joyshop list-keys`)", name),
+    }
+}
+
+/// Edit distance between two names.
+fn distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = if ca == *cb {
+                diagonal
+            } else {
+                1 + diagonal.min(above).min(row[j])
+            };
+            diagonal = above;
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chord(key: Key, modifiers: &[Key]) -> Chord {
+        Chord {
+            key,
+            modifiers: modifiers.to_vec(),
+        }
+    }
+
+    #[test]
+    fn chords_are_modifiers_then_a_key() {
+        assert_eq!(
+            parse_chord("ctrl + Shift+z"),
+            Ok(chord(Key::Z, &[Key::Control, Key::Shift]))
+        );
+        assert_eq!(
+            parse_chord("Ctrl+Ctrl+Z"),
+            Ok(chord(Key::Z, &[Key::Control]))
+        );
+        assert_eq!(parse_chord("LShift"), Ok(chord(Key::LeftShift, &[])));
+        assert_eq!(
+            parse_chord("Ctrl+["),
+            Ok(chord(Key::Oem4OpenSquareOrCurlyBracket, &[Key::Control]))
+        );
+        assert_eq!(
+            parse_chord("Ctrl++"),
+            Ok(chord(Key::OemPlus, &[Key::Control]))
+        );
+        assert_eq!(parse_chord("+"), Ok(chord(Key::OemPlus, &[])));
+    }
+
+    #[test]
+    fn broken_chords_say_why() {
+        assert_eq!(parse_chord(" "), Err("empty key combination".to_string()));
+        assert_eq!(
+            parse_chord("Ctrl+"),
+            Err("missing key in `Ctrl+`".to_string())
+        );
+        assert_eq!(
+            parse_chord("A+B"),
+            Err("more than one key in `A+B`, `A` isn't a modifier".to_string())
+        );
+        assert_eq!(
+            parse_chord("Ctrl+Escpe"),
+            Err("unknown key `Escpe` (did you mean `Escape`?)".to_string())
+        );
+        assert_eq!(
+            parse_key("Nothing like a key"),
+            Err("unknown key `Nothing like a key` (see `joyshop list-keys`)".to_string())
+        );
+    }
+
+    #[test]
+    fn chords_are_written_the_way_they_are_read() {
+        let written = [
+            "Ctrl+Shift+Z",
+            "Alt+[",
+            "Win+PrintScreen",
+            "RShift",
+            "Ctrl+Alt",
+            "None",
+        ];
+        for text in written.iter() {
+            let parsed = parse_chord(text).unwrap();
+            assert_eq!(format_chord(&parsed), *text);
+        }
+        assert_eq!(format_chord(&chord(Key::Zero, &[Key::Control])), "Ctrl+0");
+        assert_eq!(format_chord(&chord(Key::None, &[Key::Alt])), "Alt");
+    }
+
+    #[test]
+    fn distance_counts_edits() {
+        assert_eq!(distance("escape", "escape"), 0);
+        assert_eq!(distance("escpe", "escape"), 1);
+        assert_eq!(distance("tab", "tba"), 2);
+        assert_eq!(distance("", "del"), 3);
+    }
+}
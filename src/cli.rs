@@ -0,0 +1,657 @@
+use crate::bundle::{import_bundle, Conflict, ProfileBundle};
+use crate::config_format::ConfigFormat;
+use crate::config_path::resolve_config_path;
+use crate::configuration::{
+    create_default, load_config, load_config_or_default, write_config, Config, Key,
+};
+use crate::control::{send_request, serve_control, ControlState};
+use crate::controller::enable_monitor;
+use crate::events::{log_events, EventBus};
+use crate::http_server::serve_http;
+use crate::joyshop::run_joyshop;
+use crate::midi::{open_if_used, output_names};
+use crate::migration::migrate_file;
+use crate::presets::{preset, preset_names};
+use crate::schema::config_schema;
+use crate::schema::{write_schema, SCHEMA_FILE};
+use crate::validation::{validate_config, Severity};
+use joycon_rs::prelude::*;
+use std::fs::{read_to_string, write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::{sleep, spawn};
+use std::time::Duration;
+
+pub const USAGE: &str = "\
+Usage: joyshop [COMMAND] [OPTIONS]
+
+Commands:
+  run                    Send keys for the connected controllers (default)
+  check-config           Check the config file and exit
+  migrate-config         Upgrade the config file to the format of this joyshop, keeping a backup
+  print-default-config   Print the default config
+  print-schema           Print the JSON Schema of the config
+  list-keys              Print every key name that can be bound
+  list-presets           Print the built-in presets
+  list-devices           Print the connected controllers
+  monitor                Print controller input and the actions it would fire, without sending keys
+  convert <path>         Write the config to <path>, as JSON, TOML or YAML by its extension
+  export-preset <name> <path>
+                         Write a built-in preset to <path>, to edit it as a config file
+  export-profile <name> <path>
+                         Write a profile to <path>, to share it
+  import-profile <path>  Add the profile in <path> to the config file
+  control <request>      Send a request to the running joyshop and print the response
+  help                   Print this help
+
+Options:
+  --config <path>        Config file to use
+  --profile <name>       Profile controllers start with
+  --preset <name>        Run with a built-in preset instead of the config file
+  --author <name>        Author export-profile writes in the file
+  --app <name>           App export-profile writes the profile is for
+  --as <name>            Name import-profile gives the profile
+  --replace              Let import-profile replace a profile with the same name
+
+The config file is --config, or else $JOYSHOP_CONFIG, or else settings.json,
+settings.toml or settings.yaml next to joyshop, or else in the user's config
+directory, where a new one is written when there's none.";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Command {
+    Run,
+    CheckConfig,
+    MigrateConfig,
+    PrintDefaultConfig,
+    PrintSchema,
+    ListKeys,
+    ListPresets,
+    ListDevices,
+    Monitor,
+    Convert,
+    ExportPreset,
+    ExportProfile,
+    ImportProfile,
+    Control,
+    Help,
+}
+
+#[derive(Debug)]
+pub struct Options {
+    pub command: Command,
+    pub config_path: PathBuf,
+    /// Profile controllers start with, and the one `export-profile` writes.
+    pub profile: Option<String>,
+    /// Preset `run` uses instead of the config file, and the one `export-preset` writes.
+    pub preset: Option<String>,
+    /// Where `convert`, `export-preset` and `export-profile` write to.
+    pub output: Option<PathBuf>,
+    /// File `import-profile` reads.
+    pub input: Option<PathBuf>,
+    pub author: Option<String>,
+    pub app: Option<String>,
+    /// Name `import-profile` gives the profile instead of its own.
+    pub rename: Option<String>,
+    pub conflict: Conflict,
+    /// What `control` sends.
+    pub request: Option<String>,
+}
+
+pub fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Options, String> {
+    let mut command = None;
+    let mut config_path = None;
+    let mut profile = None;
+    let mut preset = None;
+    let mut output = None;
+    let mut input = None;
+    let mut author = None;
+    let mut app = None;
+    let mut rename = None;
+    let mut conflict = Conflict::Refuse;
+    let mut request = None;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = |option: &str| {
+            args.next()
+                .ok_or_else(|| format!("{} needs a value", option))
+        };
+
+        match arg.as_str() {
+            "--config" => config_path = Some(PathBuf::from(value(&arg)?)),
+            "--profile" => profile = Some(value(&arg)?),
+            "--preset" => preset = Some(value(&arg)?),
+            "--author" => author = Some(value(&arg)?),
+            "--app" => app = Some(value(&arg)?),
+            "--as" => rename = Some(value(&arg)?),
+            "--replace" => conflict = Conflict::Replace,
+            "-h" | "--help" => command = Some(Command::Help),
+            _ if arg.starts_with('-') => return Err(format!("unknown option: {}", arg)),
+            _ if command == Some(Command::ExportPreset) && preset.is_none() => preset = Some(arg),
+            _ if command == Some(Command::ExportProfile) && profile.is_none() => {
+                profile = Some(arg)
+            }
+            _ if command == Some(Command::ImportProfile) && input.is_none() => {
+                input = Some(PathBuf::from(arg))
+            }
+            _ if command == Some(Command::Control) && request.is_none() => request = Some(arg),
+            _ if matches!(
+                command,
+                Some(Command::Convert) | Some(Command::ExportPreset) | Some(Command::ExportProfile)
+            ) && output.is_none() =>
+            {
+                output = Some(PathBuf::from(arg))
+            }
+            _ if command.is_some() => return Err(format!("unexpected argument: {}", arg)),
+            _ => {
+                command = Some(match arg.as_str() {
+                    "run" => Command::Run,
+                    "check-config" => Command::CheckConfig,
+                    "migrate-config" => Command::MigrateConfig,
+                    "print-default-config" => Command::PrintDefaultConfig,
+                    "print-schema" => Command::PrintSchema,
+                    "list-keys" => Command::ListKeys,
+                    "list-presets" => Command::ListPresets,
+                    "list-devices" => Command::ListDevices,
+                    "monitor" => Command::Monitor,
+                    "convert" => Command::Convert,
+                    "export-preset" => Command::ExportPreset,
+                    "export-profile" => Command::ExportProfile,
+                    "import-profile" => Command::ImportProfile,
+                    "control" => Command::Control,
+                    "help" => Command::Help,
+                    _ => return Err(format!("unknown command: {}", arg)),
+                })
+            }
+        }
+    }
+
+    let command = command.unwrap_or(Command::Run);
+    if command == Command::Convert && output.is_none() {
+        return Err("convert needs the path to write to".into());
+    }
+    if command == Command::ExportPreset && output.is_none() {
+        return Err("export-preset needs the name of a preset and the path to write to".into());
+    }
+    if command == Command::ExportProfile && output.is_none() {
+        return Err("export-profile needs the name of a profile and the path to write to".into());
+    }
+    if command == Command::ImportProfile && input.is_none() {
+        return Err("import-profile needs the path of the profile".into());
+    }
+    if command == Command::Control && request.is_none() {
+        return Err("control needs a request, like list_devices".into());
+    }
+    Ok(Options {
+        command,
+        config_path: resolve_config_path(config_path),
+        profile,
+        preset,
+        output,
+        input,
+        author,
+        app,
+        rename,
+        conflict,
+        request,
+    })
+}
+
+/// Runs the command and returns the exit code.
+pub fn run_command(options: Options) -> i32 {
+    match options.command {
+        Command::Run => run(&options, false),
+        Command::Monitor => run(&options, true),
+        Command::CheckConfig => check_config(&options),
+        Command::MigrateConfig => match migrate_file(&options.config_path) {
+            Ok(true) => 0,
+            Ok(false) => {
+                println!("{} needs no migration", options.config_path.display());
+                0
+            }
+            Err(e) => {
+                println!("{}", e);
+                1
+            }
+        },
+        Command::PrintDefaultConfig => {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&create_default()).unwrap()
+            );
+            0
+        }
+        Command::PrintSchema => {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&config_schema()).unwrap()
+            );
+            0
+        }
+        Command::ListKeys => {
+            for key in Key::ALL.iter() {
+                println!("{}", key);
+            }
+            0
+        }
+        Command::ListPresets => {
+            for name in preset_names() {
+                println!("{}", name);
+            }
+            0
+        }
+        Command::ListDevices => list_devices(),
+        Command::Convert => convert(&options),
+        Command::ExportPreset => export_preset(&options),
+        Command::ExportProfile => export_profile(&options),
+        Command::ImportProfile => import_profile(&options),
+        Command::Control => match send_request(options.request.as_ref().unwrap()) {
+            Ok(true) => 0,
+            Ok(false) => 1,
+            Err(e) => {
+                println!("{}", e);
+                1
+            }
+        },
+        Command::Help => {
+            println!("{}", USAGE);
+            0
+        }
+    }
+}
+
+fn run(options: &Options, monitor: bool) -> i32 {
+    let config = match &options.preset {
+        Some(name) => match find_preset(name) {
+            Some(config) => {
+                println!("[Config] preset {}", name);
+                Arc::new(RwLock::new(Box::new(config)))
+            }
+            None => return 1,
+        },
+        None => {
+            println!("[Config] {}", options.config_path.display());
+            load_config_or_default(&options.config_path)
+        }
+    };
+    if let Some(profile) = &options.profile {
+        let mut config = config.write().unwrap();
+        if config.profile_index(profile).is_none() {
+            println!("unknown profile: {}", profile);
+            return 1;
+        }
+        config.start_profile = Some(profile.clone());
+    }
+    if monitor {
+        enable_monitor();
+    } else if let Err(e) = open_if_used(&config.read().unwrap()) {
+        println!("{}", e);
+    }
+
+    // Subscribed before the devices are read, so no event is missed.
+    let events = EventBus::new();
+    let log = events.subscribe();
+    #[cfg(windows)]
+    let tooltip = (!monitor).then(|| events.subscribe());
+
+    let control = ControlState {
+        config: config.clone(),
+        config_path: options
+            .preset
+            .is_none()
+            .then(|| options.config_path.clone()),
+        events: events.clone(),
+        start_profile: Arc::new(Mutex::new(options.profile.clone())),
+    };
+    if let Some(port) = config.read().unwrap().http_port {
+        let control = control.clone();
+        spawn(move || serve_http(port, control));
+    }
+    spawn(move || serve_control(control));
+
+    let logic_config = config.clone();
+    #[cfg(target_os = "linux")]
+    {
+        let config = config.clone();
+        let events = events.clone();
+        spawn(move || crate::gamepad::run_gamepads(config, events));
+    }
+
+    spawn(move || run_joyshop(logic_config, events));
+
+    #[cfg(windows)]
+    {
+        if let Some(tooltip) = tooltip {
+            spawn(move || log_events(log));
+            let show_tooltip = config.read().unwrap().show_tooltip;
+            crate::ui::process_ui(tooltip, show_tooltip);
+            return 0;
+        }
+    }
+
+    // Without a tooltip, actions are only logged.
+    log_events(log);
+    0
+}
+
+fn check_config(options: &Options) -> i32 {
+    let (config, diagnostics) = match load_config(&options.config_path) {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            println!("{}", e);
+            return 1;
+        }
+    };
+
+    for diagnostic in diagnostics.iter() {
+        println!("{}", diagnostic);
+    }
+    let mut is_valid = diagnostics.iter().all(|d| d.severity != Severity::Error);
+    if let Some(profile) = &options.profile {
+        if config.profile_index(profile).is_none() {
+            println!("unknown profile: {}", profile);
+            is_valid = false;
+        }
+    }
+    if !is_valid {
+        return 1;
+    }
+
+    println!(
+        "{} is valid ({} profiles)",
+        options.config_path.display(),
+        config.profile_count()
+    );
+    0
+}
+
+/// Writes the config file as it is in another format, without filling in the defaults.
+fn convert(options: &Options) -> i32 {
+    let output = options.output.as_ref().unwrap();
+    if output.exists() {
+        println!("{} already exists", output.display());
+        return 1;
+    }
+
+    let text = match read_to_string(&options.config_path) {
+        Ok(text) => text,
+        Err(e) => {
+            println!("couldn't load file error: {}", e);
+            return 1;
+        }
+    };
+    let (input_format, output_format) = match (
+        ConfigFormat::from_path(&options.config_path),
+        ConfigFormat::from_path(output),
+    ) {
+        (Ok(input), Ok(output)) => (input, output),
+        (Err(e), _) | (_, Err(e)) => {
+            println!("{}", e);
+            return 1;
+        }
+    };
+
+    let (value, diagnostics) = validate_config(&text, input_format, None);
+    for diagnostic in diagnostics.iter() {
+        println!("{}", diagnostic);
+    }
+    let value = match value {
+        Some(value) if diagnostics.iter().all(|d| d.severity != Severity::Error) => value,
+        _ => {
+            println!("fix the errors in {} first", options.config_path.display());
+            return 1;
+        }
+    };
+
+    let written = output_format
+        .write(&value)
+        .and_then(|text| write(output, text).map_err(|e| e.to_string()));
+    match written {
+        Ok(()) => {
+            println!(
+                "{} written to {}",
+                options.config_path.display(),
+                output.display()
+            );
+            0
+        }
+        Err(e) => {
+            println!("couldn't write {} error: {}", output.display(), e);
+            1
+        }
+    }
+}
+
+/// Writes a built-in preset with every setting, so it can be edited and used as the config file.
+fn export_preset(options: &Options) -> i32 {
+    let output = options.output.as_ref().unwrap();
+    if output.exists() {
+        println!("{} already exists", output.display());
+        return 1;
+    }
+    let name = options.preset.as_ref().unwrap();
+    let mut config = match find_preset(name) {
+        Some(config) => config,
+        None => return 1,
+    };
+
+    if ConfigFormat::from_path(output) == Ok(ConfigFormat::Json) {
+        config.schema = Some(SCHEMA_FILE.into());
+    }
+    match write_config(output, &config) {
+        Ok(()) => {
+            println!("{} written to {}", name, output.display());
+            write_schema(output, &config);
+            0
+        }
+        Err(e) => {
+            println!("{}", e);
+            1
+        }
+    }
+}
+
+/// Writes a profile of the config file with the haptic presets it plays, to share it.
+fn export_profile(options: &Options) -> i32 {
+    let output = options.output.as_ref().unwrap();
+    if output.exists() {
+        println!("{} already exists", output.display());
+        return 1;
+    }
+    let (config, diagnostics) = match load_config(&options.config_path) {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            println!("{}", e);
+            return 1;
+        }
+    };
+    for diagnostic in diagnostics.iter() {
+        println!("{}", diagnostic);
+    }
+    if diagnostics.iter().any(|d| d.severity == Severity::Error) {
+        println!("fix the errors in {} first", options.config_path.display());
+        return 1;
+    }
+
+    let name = options.profile.as_ref().unwrap();
+    let exported = ProfileBundle::new(&config, name, options.author.clone(), options.app.clone())
+        .and_then(|bundle| bundle.write(output));
+    match exported {
+        Ok(()) => {
+            println!("{} written to {}", name, output.display());
+            0
+        }
+        Err(e) => {
+            println!("{}", e);
+            1
+        }
+    }
+}
+
+/// Adds the profile of a file `export-profile` wrote to the config file.
+fn import_profile(options: &Options) -> i32 {
+    let bundle = match ProfileBundle::read(options.input.as_ref().unwrap()) {
+        Ok(bundle) => bundle,
+        Err(e) => {
+            println!("{}", e);
+            return 1;
+        }
+    };
+    println!("{}", bundle.describe());
+
+    match import_bundle(
+        &options.config_path,
+        bundle,
+        options.rename.clone(),
+        options.conflict,
+    ) {
+        Ok(name) => {
+            println!("{} imported into {}", name, options.config_path.display());
+            0
+        }
+        Err(e) => {
+            println!("{}", e);
+            1
+        }
+    }
+}
+
+fn find_preset(name: &str) -> Option<Config> {
+    let config = preset(name);
+    if config.is_none() {
+        println!(
+            "unknown preset: {}, the presets are {}",
+            name,
+            preset_names().join(", ")
+        );
+    }
+    config
+}
+
+fn list_devices() -> i32 {
+    let manager = JoyConManager::get_instance();
+    // Give the manager a moment to find the devices that are already connected.
+    sleep(Duration::from_millis(500));
+
+    let devices = match manager.lock() {
+        Ok(manager) => manager.managed_devices(),
+        Err(_) => return 1,
+    };
+    for device in devices {
+        if let Ok(device) = device.lock() {
+            if device.is_connected() {
+                println!("{:?} {}", device.device_type(), device.serial_number());
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    for (path, name) in crate::gamepad::list_gamepads() {
+        println!("Gamepad {} ({})", name, path.display());
+    }
+    for name in output_names() {
+        println!("MIDI output {}", name);
+    }
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Options, String> {
+        parse_args(args.split_whitespace().map(String::from))
+    }
+
+    #[test]
+    fn every_command_is_parsed() {
+        let commands = [
+            ("", Command::Run),
+            ("run", Command::Run),
+            ("check-config", Command::CheckConfig),
+            ("migrate-config", Command::MigrateConfig),
+            ("print-default-config", Command::PrintDefaultConfig),
+            ("print-schema", Command::PrintSchema),
+            ("list-keys", Command::ListKeys),
+            ("list-presets", Command::ListPresets),
+            ("list-devices", Command::ListDevices),
+            ("monitor", Command::Monitor),
+            ("convert settings.toml", Command::Convert),
+            ("export-preset Krita krita.json", Command::ExportPreset),
+            ("export-profile Paint paint.json", Command::ExportProfile),
+            ("import-profile paint.json", Command::ImportProfile),
+            ("control list_devices", Command::Control),
+            ("help", Command::Help),
+            ("--help", Command::Help),
+            ("run -h", Command::Help),
+        ];
+        for (args, command) in commands.iter() {
+            assert_eq!(parse(args).map(|o| o.command), Ok(*command), "{}", args);
+        }
+    }
+
+    #[test]
+    fn options_go_anywhere_around_the_arguments() {
+        let options = parse("--config my.yaml run --profile Paint --preset Krita").unwrap();
+        assert_eq!(options.config_path, PathBuf::from("my.yaml"));
+        assert_eq!(options.profile.as_deref(), Some("Paint"));
+        assert_eq!(options.preset.as_deref(), Some("Krita"));
+
+        let options = parse("--author Ann export-profile Paint --app Krita paint.json").unwrap();
+        assert_eq!(options.profile.as_deref(), Some("Paint"));
+        assert_eq!(options.output, Some(PathBuf::from("paint.json")));
+        assert_eq!(options.author.as_deref(), Some("Ann"));
+        assert_eq!(options.app.as_deref(), Some("Krita"));
+
+        let options = parse("--replace import-profile --as Sketch paint.json").unwrap();
+        assert_eq!(options.input, Some(PathBuf::from("paint.json")));
+        assert_eq!(options.rename.as_deref(), Some("Sketch"));
+        assert_eq!(options.conflict, Conflict::Replace);
+        assert_eq!(
+            parse("import-profile a.json").unwrap().conflict,
+            Conflict::Refuse
+        );
+
+        let options = parse("export-preset Krita krita.toml").unwrap();
+        assert_eq!(options.preset.as_deref(), Some("Krita"));
+        assert_eq!(options.output, Some(PathBuf::from("krita.toml")));
+        assert_eq!(
+            parse("convert out.json").unwrap().output,
+            Some(PathBuf::from("out.json"))
+        );
+        assert_eq!(
+            parse("control get_battery").unwrap().request.as_deref(),
+            Some("get_battery")
+        );
+    }
+
+    #[test]
+    fn mistakes_are_reported() {
+        let mistakes = [
+            ("--verbose", "unknown option: --verbose"),
+            ("run --profile", "--profile needs a value"),
+            ("--config", "--config needs a value"),
+            ("import-profile a.json --as", "--as needs a value"),
+            ("start", "unknown command: start"),
+            ("list-keys all", "unexpected argument: all"),
+            ("convert a.json b.json", "unexpected argument: b.json"),
+            ("convert", "convert needs the path to write to"),
+            (
+                "export-preset Krita",
+                "export-preset needs the name of a preset and the path to write to",
+            ),
+            (
+                "export-profile Paint",
+                "export-profile needs the name of a profile and the path to write to",
+            ),
+            (
+                "import-profile",
+                "import-profile needs the path of the profile",
+            ),
+            ("control", "control needs a request, like list_devices"),
+        ];
+        for (args, error) in mistakes.iter() {
+            assert_eq!(parse(args).err().as_deref(), Some(*error), "{}", args);
+        }
+    }
+}
@@ -0,0 +1,161 @@
+use serde_json::Value;
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+
+/// File formats settings can be written in. They all hold the same `Config`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Json,
+    Toml,
+    Yaml,
+}
+
+/// Config files looked for when no `--config` is given, in this order.
+pub const DEFAULT_CONFIG_FILES: [&str; 4] = [
+    "settings.json",
+    "settings.toml",
+    "settings.yaml",
+    "settings.yml",
+];
+
+impl ConfigFormat {
+    /// The format of a config file, by its extension.
+    pub fn from_path(path: &Path) -> Result<ConfigFormat, String> {
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or_default()
+            .to_lowercase();
+        match extension.as_str() {
+            "json" => Ok(ConfigFormat::Json),
+            "toml" => Ok(ConfigFormat::Toml),
+            "yaml" | "yml" => Ok(ConfigFormat::Yaml),
+            _ => Err(format!(
+                "{} isn't a config file, use .json, .toml or .yaml",
+                path.display()
+            )),
+        }
+    }
+
+    /// Reads a config file, returning the line of the mistake when it can't.
+    pub fn parse(self, text: &str) -> Result<Value, (Option<usize>, String)> {
+        match self {
+            ConfigFormat::Json => {
+                serde_json::from_str(text).map_err(|e| (Some(e.line()), e.to_string()))
+            }
+            ConfigFormat::Toml => toml::from_str(text).map_err(|e| {
+                let line = e.span().map(|span| line_at(text, span.start));
+                (line, e.message().to_string())
+            }),
+            ConfigFormat::Yaml => serde_yaml::from_str(text).map_err(|e| {
+                let line = e.location().map(|location| location.line());
+                (line, e.to_string())
+            }),
+        }
+    }
+
+    pub fn write(self, value: &Value) -> Result<String, String> {
+        let value = tidy(value, self == ConfigFormat::Toml);
+        match self {
+            ConfigFormat::Json => Ok(serde_json::to_string_pretty(&value).unwrap()),
+            ConfigFormat::Toml => toml::to_string_pretty(&value).map_err(|e| e.to_string()),
+            ConfigFormat::Yaml => serde_yaml::to_string(&value).map_err(|e| e.to_string()),
+        }
+    }
+}
+
+impl Display for ConfigFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ConfigFormat::Json => "JSON",
+            ConfigFormat::Toml => "TOML",
+            ConfigFormat::Yaml => "YAML",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+fn line_at(text: &str, offset: usize) -> usize {
+    text.as_bytes()[..offset.min(text.len())]
+        .iter()
+        .filter(|b| **b == b'\n')
+        .count()
+        + 1
+}
+
+/// Writes the `f32` settings as they were typed, `0.9` instead of `0.8999999761581421`.
+/// TOML has no null, so nulls are left out for it, a missing setting is the default anyway.
+fn tidy(value: &Value, without_nulls: bool) -> Value {
+    match value {
+        Value::Object(object) => Value::Object(
+            object
+                .iter()
+                .filter(|(_, v)| !(without_nulls && v.is_null()))
+                .map(|(k, v)| (k.clone(), tidy(v, without_nulls)))
+                .collect(),
+        ),
+        Value::Array(array) => Value::Array(array.iter().map(|v| tidy(v, without_nulls)).collect()),
+        Value::Number(number) if number.is_f64() => {
+            let float = number.as_f64().unwrap();
+            if float as f32 as f64 == float {
+                let short = (float as f32).to_string().parse::<f64>().unwrap();
+                short.into()
+            } else {
+                value.clone()
+            }
+        }
+        _ => value.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::create_default;
+    use serde_json::json;
+
+    const FORMATS: [ConfigFormat; 3] = [ConfigFormat::Json, ConfigFormat::Toml, ConfigFormat::Yaml];
+
+    #[test]
+    fn formats_go_by_extension() {
+        let format = |path: &str| ConfigFormat::from_path(Path::new(path));
+        assert_eq!(format("settings.json"), Ok(ConfigFormat::Json));
+        assert_eq!(format("dir/Settings.TOML"), Ok(ConfigFormat::Toml));
+        assert_eq!(format("settings.yml"), Ok(ConfigFormat::Yaml));
+        assert!(format("settings").is_err());
+    }
+
+    #[test]
+    fn the_default_config_reads_back_the_same_in_every_format() {
+        let value = serde_json::to_value(create_default()).unwrap();
+        let tidied = tidy(&value, false);
+        for format in FORMATS.iter() {
+            let text = format.write(&value).unwrap();
+            assert_eq!(format.parse(&text).unwrap(), tidied, "{}", format);
+        }
+    }
+
+    #[test]
+    fn floats_are_written_as_typed_and_toml_leaves_out_nulls() {
+        let value = json!({ "amplitude": 0.9f32, "start_profile": null });
+        assert_eq!(
+            ConfigFormat::Json.write(&value).unwrap(),
+            "{\n  \"amplitude\": 0.9,\n  \"start_profile\": null\n}"
+        );
+        assert_eq!(
+            ConfigFormat::Toml.write(&value).unwrap(),
+            "amplitude = 0.9\n"
+        );
+    }
+
+    #[test]
+    fn mistakes_have_their_line() {
+        let line = |format: ConfigFormat, text: &str| format.parse(text).unwrap_err().0;
+        assert_eq!(
+            line(ConfigFormat::Json, "{\n  \"a\": 1,\n  \"b\": \n}"),
+            Some(4)
+        );
+        assert_eq!(line(ConfigFormat::Toml, "a = 1\nb = \nc = 2\n"), Some(2));
+        assert_eq!(line(ConfigFormat::Yaml, "a: 1\nb: [\n"), Some(3));
+    }
+}
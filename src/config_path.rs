@@ -0,0 +1,170 @@
+use crate::config_format::DEFAULT_CONFIG_FILES;
+use std::env;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+/// Environment variable with the path of the config file, used when there's no `--config`.
+pub const CONFIG_ENV: &str = "JOYSHOP_CONFIG";
+
+/// Finds the config file, the first of:
+/// 1. `--config`
+/// 2. `JOYSHOP_CONFIG`
+/// 3. a settings file next to the executable (portable mode)
+/// 4. a settings file in the user's config directory
+///
+/// When there's none yet, it's settings.json in the user's config directory,
+/// or next to the executable when there's no such directory.
+pub fn resolve_config_path(flag: Option<PathBuf>) -> PathBuf {
+    if let Some(path) = chosen_config_path(flag, env::var_os(CONFIG_ENV)) {
+        return path;
+    }
+
+    let executable_dir = env::current_exe()
+        .ok()
+        .and_then(|executable| executable.parent().map(Path::to_path_buf));
+    let path = search_config_path(executable_dir, user_config_dir());
+    // Older joyshops read the settings of the folder they were started from.
+    if let Some(unused) = env::current_dir()
+        .ok()
+        .and_then(|dir| unused_config(&dir, &path))
+    {
+        eprintln!(
+            "[Config] {} isn't read, settings are in {}. Use --config {} to read it",
+            unused.display(),
+            path.display(),
+            unused.display()
+        );
+    }
+    path
+}
+
+/// `--config`, or else the path in `JOYSHOP_CONFIG`.
+fn chosen_config_path(flag: Option<PathBuf>, env_path: Option<OsString>) -> Option<PathBuf> {
+    flag.or_else(|| env_path.filter(|path| !path.is_empty()).map(PathBuf::from))
+}
+
+/// The config in the executable or user directory, or where a new one goes.
+fn search_config_path(executable_dir: Option<PathBuf>, user_dir: Option<PathBuf>) -> PathBuf {
+    for dir in executable_dir.iter().chain(user_dir.iter()) {
+        if let Some(path) = find_config(dir) {
+            return path;
+        }
+    }
+
+    user_dir
+        .or(executable_dir)
+        .unwrap_or_default()
+        .join(DEFAULT_CONFIG_FILES[0])
+}
+
+/// A config in `dir` that isn't the one at `path`.
+fn unused_config(dir: &Path, path: &Path) -> Option<PathBuf> {
+    let found = find_config(dir)?;
+    match (found.canonicalize(), path.canonicalize()) {
+        (Ok(found), Ok(path)) if found == path => None,
+        _ => Some(found),
+    }
+}
+
+/// The first of `DEFAULT_CONFIG_FILES` in `dir`.
+fn find_config(dir: &Path) -> Option<PathBuf> {
+    DEFAULT_CONFIG_FILES
+        .iter()
+        .map(|name| dir.join(name))
+        .find(|path| path.is_file())
+}
+
+/// `%APPDATA%\joyshop`.
+#[cfg(windows)]
+fn user_config_dir() -> Option<PathBuf> {
+    env::var_os("APPDATA")
+        .filter(|dir| !dir.is_empty())
+        .map(|dir| PathBuf::from(dir).join("joyshop"))
+}
+
+/// `$XDG_CONFIG_HOME/joyshop`, which is `~/.config/joyshop` by default.
+#[cfg(not(windows))]
+fn user_config_dir() -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .map(|dir| dir.join("joyshop"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{create_dir_all, remove_dir_all, write};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!(
+            "joyshop-config-path-{}-{}",
+            name,
+            std::process::id()
+        ));
+        create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn the_flag_and_then_the_environment_come_first() {
+        let flag = || Some(PathBuf::from("flag.toml"));
+        let env_path = || Some(OsString::from("env.yaml"));
+        assert_eq!(
+            chosen_config_path(flag(), env_path()),
+            Some(PathBuf::from("flag.toml"))
+        );
+        assert_eq!(
+            chosen_config_path(None, env_path()),
+            Some(PathBuf::from("env.yaml"))
+        );
+        assert_eq!(chosen_config_path(None, Some(OsString::new())), None);
+        assert_eq!(chosen_config_path(None, None), None);
+        assert_eq!(resolve_config_path(flag()), PathBuf::from("flag.toml"));
+    }
+
+    #[test]
+    fn the_executable_dir_comes_before_the_user_dir() {
+        let executable_dir = temp_dir("executable");
+        let user_dir = temp_dir("user");
+        let search = || search_config_path(Some(executable_dir.clone()), Some(user_dir.clone()));
+
+        let new = search();
+        write(user_dir.join("settings.yaml"), "").unwrap();
+        let in_user_dir = search();
+        write(executable_dir.join("settings.toml"), "").unwrap();
+        write(executable_dir.join("settings.json"), "").unwrap();
+        let in_executable_dir = search();
+        let without_user_dir = search_config_path(Some(user_dir.clone()), None);
+        remove_dir_all(&executable_dir).unwrap();
+        remove_dir_all(&user_dir).unwrap();
+
+        assert_eq!(new, user_dir.join("settings.json"));
+        assert_eq!(in_user_dir, user_dir.join("settings.yaml"));
+        assert_eq!(in_executable_dir, executable_dir.join("settings.json"));
+        assert_eq!(without_user_dir, user_dir.join("settings.yaml"));
+        assert_eq!(
+            search_config_path(None, None),
+            PathBuf::from("settings.json")
+        );
+    }
+
+    #[test]
+    fn settings_of_the_working_dir_are_noticed_when_unused() {
+        let working_dir = temp_dir("working");
+        let user_dir = temp_dir("working-user");
+        let used = user_dir.join("settings.json");
+
+        let without_any = unused_config(&working_dir, &used);
+        write(working_dir.join("settings.json"), "").unwrap();
+        let unused = unused_config(&working_dir, &used);
+        let same = unused_config(&working_dir, &working_dir.join("settings.json"));
+        remove_dir_all(&working_dir).unwrap();
+        remove_dir_all(&user_dir).unwrap();
+
+        assert_eq!(without_any, None);
+        assert_eq!(unused, Some(working_dir.join("settings.json")));
+        assert_eq!(same, None);
+    }
+}
@@ -1,11 +1,21 @@
-use serde::{Deserialize, Serialize};
-use std::fmt::Display;
+use crate::chord::{format_chord, parse_chord, parse_key, Chord};
+use crate::config_format::ConfigFormat;
+use crate::migration::{migrate_file, CONFIG_VERSION};
+use crate::mirror::apply_mirror;
+use crate::schema::{write_schema, SCHEMA_FILE};
+use crate::validation::{validate_config, Diagnostic, Severity, PROFILE_SETTINGS};
+use schemars::JsonSchema;
+use serde::de::value::MapAccessDeserializer;
+use serde::de::{Error, MapAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
 use std::fs::read_to_string;
 use std::path::Path;
 use std::sync::{Arc, RwLock};
-use winapi::_core::fmt::Formatter;
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Key {
     None = 0x00,
     Cancel = 0x03,
@@ -191,32 +201,662 @@ pub enum Key {
     OemClear = 0xFE,
 }
 
+impl Key {
+    /// Every key, in declaration order.
+    pub const ALL: [Key; 170] = [
+        Key::None,
+        Key::Cancel,
+        Key::MouseLeftButton,
+        Key::MouseRightButton,
+        Key::MouseMiddleButton,
+        Key::MouseX1Button,
+        Key::MouseX2Button,
+        Key::BackSpace,
+        Key::Tab,
+        Key::Clear,
+        Key::Enter,
+        Key::Shift,
+        Key::Control,
+        Key::Alt,
+        Key::Pause,
+        Key::CapsLock,
+        Key::ImeKanaOrHangul,
+        Key::ImeJunja,
+        Key::ImeFinal,
+        Key::ImeKanjiOrHanja,
+        Key::Escape,
+        Key::ImeConvert,
+        Key::ImeNonConvert,
+        Key::ImeAccept,
+        Key::ImeModeChange,
+        Key::Space,
+        Key::PageUp,
+        Key::PageDown,
+        Key::End,
+        Key::Home,
+        Key::Left,
+        Key::Up,
+        Key::Right,
+        Key::Down,
+        Key::Select,
+        Key::Print,
+        Key::Execute,
+        Key::PrintScreen,
+        Key::Insert,
+        Key::Delete,
+        Key::Help,
+        Key::Zero,
+        Key::One,
+        Key::Two,
+        Key::Three,
+        Key::Four,
+        Key::Five,
+        Key::Six,
+        Key::Seven,
+        Key::Eight,
+        Key::Nine,
+        Key::A,
+        Key::B,
+        Key::C,
+        Key::D,
+        Key::E,
+        Key::F,
+        Key::G,
+        Key::H,
+        Key::I,
+        Key::J,
+        Key::K,
+        Key::L,
+        Key::M,
+        Key::N,
+        Key::O,
+        Key::P,
+        Key::Q,
+        Key::R,
+        Key::S,
+        Key::T,
+        Key::U,
+        Key::V,
+        Key::W,
+        Key::X,
+        Key::Y,
+        Key::Z,
+        Key::LeftWindows,
+        Key::RightWindows,
+        Key::Application,
+        Key::Sleep,
+        Key::Numpad0,
+        Key::Numpad1,
+        Key::Numpad2,
+        Key::Numpad3,
+        Key::Numpad4,
+        Key::Numpad5,
+        Key::Numpad6,
+        Key::Numpad7,
+        Key::Numpad8,
+        Key::Numpad9,
+        Key::AsteriskOrMultiply,
+        Key::PlusOrAdd,
+        Key::Separator,
+        Key::MinusOrSubstract,
+        Key::PeriodOrDecimal,
+        Key::SlashOrDivide,
+        Key::F1,
+        Key::F2,
+        Key::F3,
+        Key::F4,
+        Key::F5,
+        Key::F6,
+        Key::F7,
+        Key::F8,
+        Key::F9,
+        Key::F10,
+        Key::F11,
+        Key::F12,
+        Key::F13,
+        Key::F14,
+        Key::F15,
+        Key::F16,
+        Key::F17,
+        Key::F18,
+        Key::F19,
+        Key::F20,
+        Key::F21,
+        Key::F22,
+        Key::F23,
+        Key::F24,
+        Key::NumLock,
+        Key::Scroll,
+        Key::LeftShift,
+        Key::RightShift,
+        Key::LeftControl,
+        Key::RightControl,
+        Key::LeftAlt,
+        Key::RightAlt,
+        Key::BrowserBack,
+        Key::BrowserForward,
+        Key::BrowserRefresh,
+        Key::BrowserStop,
+        Key::BrowserSearch,
+        Key::BrowserFavorites,
+        Key::BrowserHome,
+        Key::VolumeMute,
+        Key::VolumeDown,
+        Key::VolumeUp,
+        Key::MediaNextTrack,
+        Key::MediaPrevTrack,
+        Key::MediaStop,
+        Key::MediaPlayPause,
+        Key::LaunchMail,
+        Key::LaunchMediaSelect,
+        Key::LaunchApp1,
+        Key::LaunchApp2,
+        Key::Oem1SemiColonOrColon,
+        Key::OemPlus,
+        Key::OemComma,
+        Key::OemMinus,
+        Key::OemPeriod,
+        Key::Oem2SlashOrQuestion,
+        Key::Oem3BacktickOrTilda,
+        Key::Oem4OpenSquareOrCurlyBracket,
+        Key::Oem5BackslashOrPipe,
+        Key::Oem6CloseSquareOrCurlyBracket,
+        Key::Oem7SingleOrDoubleQuote,
+        Key::Oem8,
+        Key::Oem102,
+        Key::ImeProcessKey,
+        Key::Packet,
+        Key::Attn,
+        Key::CrSel,
+        Key::ExSel,
+        Key::EraseEof,
+        Key::Play,
+        Key::Zoom,
+        Key::Pa1,
+        Key::OemClear,
+    ];
+}
+
 impl Display for Key {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "{:?}", self)
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Keys are read by their name or one of their aliases, see `chord`.
+impl<'de> Deserialize<'de> for Key {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        parse_key(&name).map_err(D::Error::custom)
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Motor {
+    #[default]
+    Both,
+    Left,
+    Right,
+}
+
+/// One step of a rumble pattern. A segment with zero amplitude is a pause.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct HapticSegment {
+    pub frequency: f32,
+    /// 0 to 1. Higher amplitudes are played as 1.
+    pub amplitude: f32,
+    pub duration_ms: u64,
+    #[serde(default)]
+    pub motor: Motor,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub enum Haptic {
+    /// Name of an entry in `haptic_presets` (or a built-in preset).
+    Preset(String),
+    Pattern(Vec<HapticSegment>),
+}
+
+/// Written as a chord like `"Ctrl+Shift+Z"`, or as `{"name": "Redo", "keys": "Ctrl+Shift+Z"}`
+/// to give it a name or a haptic. The older `key`, `ctrl`, `alt` and `shift` fields are read too.
+#[derive(Debug, Clone)]
 pub struct KeyCombination {
     pub name: String,
     pub key: Key,
-    pub ctrl: bool,
-    pub alt: bool,
-    pub shift: bool,
+    /// Held while `key` is pressed, in this order.
+    pub modifiers: Vec<Key>,
+    pub haptic: Option<Haptic>,
+}
+
+impl KeyCombination {
+    pub fn chord(&self) -> Chord {
+        Chord {
+            key: self.key,
+            modifiers: self.modifiers.clone(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct NamedKeyCombination<'a> {
+    name: &'a str,
+    keys: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    haptic: &'a Option<Haptic>,
+}
+
+impl Serialize for KeyCombination {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let keys = format_chord(&self.chord());
+        if self.haptic.is_none() && self.name == keys {
+            return serializer.serialize_str(&keys);
+        }
+        NamedKeyCombination {
+            name: &self.name,
+            keys,
+            haptic: &self.haptic,
+        }
+        .serialize(serializer)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeyCombinationFields {
+    name: Option<String>,
+    keys: Option<String>,
+    key: Option<Key>,
+    #[serde(default)]
+    ctrl: bool,
+    #[serde(default)]
+    alt: bool,
+    #[serde(default)]
+    shift: bool,
+    #[serde(default)]
+    haptic: Option<Haptic>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+struct KeyCombinationVisitor;
+
+impl<'de> Visitor<'de> for KeyCombinationVisitor {
+    type Value = KeyCombination;
+
+    fn expecting(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "a key combination like \"Ctrl+Shift+Z\"")
+    }
+
+    fn visit_str<E: Error>(self, text: &str) -> Result<KeyCombination, E> {
+        let chord = parse_chord(text).map_err(E::custom)?;
+        Ok(KeyCombination {
+            name: format_chord(&chord),
+            key: chord.key,
+            modifiers: chord.modifiers,
+            haptic: None,
+        })
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<KeyCombination, A::Error> {
+        let fields = KeyCombinationFields::deserialize(MapAccessDeserializer::new(map))?;
+        let chord = match (fields.keys, fields.key) {
+            (Some(keys), None) => parse_chord(&keys).map_err(A::Error::custom)?,
+            (None, Some(key)) => {
+                let modifiers = [
+                    (fields.ctrl, Key::Control),
+                    (fields.alt, Key::Alt),
+                    (fields.shift, Key::Shift),
+                ];
+                Chord {
+                    key,
+                    modifiers: modifiers
+                        .iter()
+                        .filter(|(held, _)| *held)
+                        .map(|(_, modifier)| *modifier)
+                        .collect(),
+                }
+            }
+            (Some(_), Some(_)) => return Err(A::Error::custom("use either `keys` or `key`")),
+            (None, None) => return Err(A::Error::missing_field("keys")),
+        };
+        Ok(KeyCombination {
+            name: fields.name.unwrap_or_else(|| format_chord(&chord)),
+            key: chord.key,
+            modifiers: chord.modifiers,
+            haptic: fields.haptic,
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for KeyCombination {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(KeyCombinationVisitor)
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
 pub enum KeyAction {
     None,
     KeyHold(KeyCombination),
     KeyClick(KeyCombination),
+    NextProfile,
+    SwitchProfile(String),
+    ShowBatteryStatus,
+    Osc(OscMessage),
+    OscStream(OscStream),
+    MidiNote(MidiNote),
+    MidiCc(MidiCc),
+    MidiProgram(MidiProgram),
+    MidiCcStream(MidiCcStream),
+}
+
+/// An OSC message sent over UDP when the button is pressed.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct OscMessage {
+    /// Like `/composition/layers/1/clips/1/connect`.
+    pub address: String,
+    #[serde(default)]
+    pub args: Vec<OscArg>,
+    /// Args of the message sent when the button is released. Nothing is sent if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub release_args: Option<Vec<OscArg>>,
+    /// `host:port` to send to instead of `osc_target`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+}
+
+/// An argument of an OSC message. Whole numbers are sent as int32 and other numbers as float32.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum OscArg {
+    Bool(bool),
+    Int(i32),
+    Float(f32),
+    String(String),
+}
+
+/// Sends an analog input as OSC floats with every report while the button is held.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct OscStream {
+    pub address: String,
+    pub input: AnalogInput,
+    /// `host:port` to send to instead of `osc_target`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+}
+
+/// A note that's on while the button is held.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct MidiNote {
+    /// 0 to 127, 60 is middle C.
+    pub note: u8,
+    #[serde(default = "default_velocity")]
+    pub velocity: u8,
+    /// 1 to 16.
+    #[serde(default = "default_midi_channel")]
+    pub channel: u8,
+}
+
+/// A control change sent when the button is pressed.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct MidiCc {
+    /// 0 to 127.
+    pub controller: u8,
+    #[serde(default = "default_cc_value")]
+    pub value: u8,
+    /// Value sent when the button is released. Nothing is sent if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub release_value: Option<u8>,
+    #[serde(default = "default_midi_channel")]
+    pub channel: u8,
+}
+
+/// A program change sent when the button is pressed.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct MidiProgram {
+    /// 0 to 127.
+    pub program: u8,
+    #[serde(default = "default_midi_channel")]
+    pub channel: u8,
+}
+
+/// Sends an analog input as control changes while the button is held, when a value changes.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct MidiCcStream {
+    pub input: AnalogInput,
+    /// Controller of each axis, like `[1, 2]` for x and y. Axes without one aren't sent.
+    pub controllers: Vec<u8>,
+    /// Input sent as 127, with its negative sent as 0.
+    /// 1 for the stick, 360 degrees per second for the gyro and 1 G for accel if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub range: Option<f32>,
+    #[serde(default = "default_midi_channel")]
+    pub channel: u8,
+}
+
+fn default_velocity() -> u8 {
+    100
+}
+
+fn default_cc_value() -> u8 {
+    127
+}
+
+fn default_midi_channel() -> u8 {
+    1
+}
+
+/// Analog input of the side of the button.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnalogInput {
+    /// The stick, x and y from -1 to 1.
+    Stick,
+    /// Rotation around x, y and z in degrees per second.
+    Gyro,
+    /// Acceleration along x, y and z in G.
+    Accel,
+}
+
+#[derive(
+    Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord,
+)]
+pub enum BatteryLevel {
+    Empty,
+    Critical,
+    Low,
+    Medium,
+    Full,
+}
+
+/// The lowest battery level that lights each number of LEDs.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct BatteryLights {
+    pub one_light: BatteryLevel,
+    pub two_lights: BatteryLevel,
+    pub three_lights: BatteryLevel,
+    pub four_lights: BatteryLevel,
+    /// Lit LEDs flash at this level and below.
+    pub flash_at: BatteryLevel,
+}
+
+impl Default for BatteryLights {
+    fn default() -> Self {
+        BatteryLights {
+            one_light: BatteryLevel::Critical,
+            two_lights: BatteryLevel::Low,
+            three_lights: BatteryLevel::Medium,
+            four_lights: BatteryLevel::Full,
+            flash_at: BatteryLevel::Critical,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub enum PlayerLightMode {
+    Battery(BatteryLights),
+    /// Number of the active profile, the base profile being 1.
+    Profile,
+    /// Number of the controller in connection order.
+    ControllerSlot,
+    /// The `lights` of the active profile.
+    Custom,
+}
+
+impl Default for PlayerLightMode {
+    fn default() -> Self {
+        PlayerLightMode::Battery(BatteryLights::default())
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlayerLight {
+    Off,
+    On,
+    Flash,
+}
+
+/// Settings of joyshop. Settings missing from the file are the default ones.
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
+#[serde(default)]
 pub struct Config {
+    /// JSON Schema editors check the file with, joyshop keeps `settings.schema.json` up to date.
+    #[serde(rename = "$schema", skip_serializing_if = "Option::is_none")]
+    pub schema: Option<String>,
+    /// Settings file, relative to this one, or built-in preset these settings change.
+    /// Profiles with the same name are merged.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extends: Option<String>,
+    /// Format of the file, older ones are upgraded by joyshop.
+    pub version: u32,
     pub show_tooltip: bool,
+    pub haptic_presets: BTreeMap<String, Vec<HapticSegment>>,
+    pub player_lights: PlayerLightMode,
+
+    /// The base profile, which is active when a controller connects.
+    #[serde(flatten)]
+    pub profile: Profile,
+    pub profiles: Vec<Profile>,
+    pub controllers: Vec<ControllerSettings>,
+    /// Profile controllers start with, unless their settings name one. The base profile if unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_profile: Option<String>,
+    /// Event devices read as gamepads on Linux. When empty, every gamepad is used.
+    pub gamepads: Vec<String>,
+    /// Port of the HTTP and WebSocket server on 127.0.0.1. There's no server if unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub http_port: Option<u16>,
+    /// `host:port` OSC messages are sent to, `127.0.0.1:9000` if unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub osc_target: Option<String>,
+    /// Name, or part of the name, of the MIDI output to send to.
+    /// A virtual port called joyshop is made if unset, except on Windows, which can't.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub midi_port: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        create_default()
+    }
+}
 
+impl Config {
+    pub fn profile_count(&self) -> usize {
+        self.profiles.len() + 1
+    }
+
+    pub fn profile(&self, index: usize) -> &Profile {
+        match index {
+            0 => &self.profile,
+            _ => self.profiles.get(index - 1).unwrap_or(&self.profile),
+        }
+    }
+
+    pub fn profile_index(&self, name: &str) -> Option<usize> {
+        (0..self.profile_count()).find(|&i| self.profile(i).name == name)
+    }
+
+    pub fn start_profile_index(&self) -> usize {
+        match &self.start_profile {
+            Some(name) => self.profile_index(name).unwrap_or_else(|| {
+                println!("unknown profile: {}", name);
+                0
+            }),
+            None => 0,
+        }
+    }
+
+    /// Serial numbers are compared ignoring case and `:`/`-` separators, so MAC addresses
+    /// can be written either way.
+    pub fn controller_settings(&self, serial_number: &str) -> Option<&ControllerSettings> {
+        let normalize = |s: &str| {
+            s.chars()
+                .filter(|c| *c != ':' && *c != '-')
+                .collect::<String>()
+                .to_lowercase()
+        };
+        let serial_number = normalize(serial_number);
+        self.controllers
+            .iter()
+            .find(|c| normalize(&c.serial_number) == serial_number)
+    }
+}
+
+/// Settings of a controller identified by its serial number or MAC address.
+/// Controllers without settings start with the base profile.
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
+pub struct ControllerSettings {
+    pub serial_number: String,
+    /// Shown in the tooltip next to the actions this controller fires.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// The profile active when the controller connects.
+    #[serde(default)]
+    pub profile: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct Profile {
+    #[serde(default = "default_profile_name")]
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lights: Option<[PlayerLight; 4]>,
+    #[serde(flatten)]
+    pub bindings: Bindings,
+    #[serde(default)]
+    pub pro_controller: ProControllerBindings,
+    /// Copies the bindings of one JoyCon onto the other, or swaps them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mirror: Option<Mirror>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MirrorMode {
+    LeftToRight,
+    RightToLeft,
+    /// Swaps the bindings of both JoyCons, for left-handed use.
+    Swap,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum Mirror {
+    Mode(MirrorMode),
+    Custom {
+        mode: MirrorMode,
+        /// Buttons of the left JoyCon and the buttons of the right one they change places with,
+        /// over the ones at the same place, e.g. `{"up": "a"}`.
+        #[serde(default)]
+        buttons: BTreeMap<String, String>,
+    },
+}
+
+fn default_profile_name() -> String {
+    "Default".into()
+}
+
+/// Bindings of the buttons and stick slots that JoyCons and Pro Controllers both have.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+#[serde(default)]
+pub struct ButtonBindings {
     pub zl: KeyAction,
     pub l: KeyAction,
     pub minus: KeyAction,
@@ -232,8 +872,6 @@ pub struct Config {
     pub left: KeyAction,
     pub right: KeyAction,
     pub capture: KeyAction,
-    pub left_sl: KeyAction,
-    pub left_sr: KeyAction,
 
     pub zr: KeyAction,
     pub r: KeyAction,
@@ -250,25 +888,177 @@ pub struct Config {
     pub x: KeyAction,
     pub y: KeyAction,
     pub home: KeyAction,
+}
+
+impl ButtonBindings {
+    /// The binding of a button by its name in the settings.
+    pub fn binding_mut(&mut self, name: &str) -> Option<&mut KeyAction> {
+        let binding = match name {
+            "zl" => &mut self.zl,
+            "l" => &mut self.l,
+            "minus" => &mut self.minus,
+            "left_stick" => &mut self.left_stick,
+            "left_stick_top_left" => &mut self.left_stick_top_left,
+            "left_stick_top_center" => &mut self.left_stick_top_center,
+            "left_stick_top_right" => &mut self.left_stick_top_right,
+            "left_stick_bottom_left" => &mut self.left_stick_bottom_left,
+            "left_stick_bottom_center" => &mut self.left_stick_bottom_center,
+            "left_stick_bottom_right" => &mut self.left_stick_bottom_right,
+            "up" => &mut self.up,
+            "down" => &mut self.down,
+            "left" => &mut self.left,
+            "right" => &mut self.right,
+            "capture" => &mut self.capture,
+            "zr" => &mut self.zr,
+            "r" => &mut self.r,
+            "plus" => &mut self.plus,
+            "right_stick" => &mut self.right_stick,
+            "right_stick_top_left" => &mut self.right_stick_top_left,
+            "right_stick_top_center" => &mut self.right_stick_top_center,
+            "right_stick_top_right" => &mut self.right_stick_top_right,
+            "right_stick_bottom_left" => &mut self.right_stick_bottom_left,
+            "right_stick_bottom_center" => &mut self.right_stick_bottom_center,
+            "right_stick_bottom_right" => &mut self.right_stick_bottom_right,
+            "a" => &mut self.a,
+            "b" => &mut self.b,
+            "x" => &mut self.x,
+            "y" => &mut self.y,
+            "home" => &mut self.home,
+            _ => return None,
+        };
+        Some(binding)
+    }
+}
+
+/// Bindings of a pair of JoyCons. Bindings missing from a profile are the default ones.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+#[serde(default)]
+pub struct Bindings {
+    #[serde(flatten)]
+    pub buttons: ButtonBindings,
+    pub left_sl: KeyAction,
+    pub left_sr: KeyAction,
     pub right_sl: KeyAction,
     pub right_sr: KeyAction,
 }
 
-pub fn load_config_or_default() -> Arc<RwLock<Box<Config>>> {
-    let path = "settings.json";
-    let exists = Path::new(path).exists();
+impl Bindings {
+    /// The binding of a button by its name in the settings.
+    pub fn binding_mut(&mut self, name: &str) -> Option<&mut KeyAction> {
+        match name {
+            "left_sl" => Some(&mut self.left_sl),
+            "left_sr" => Some(&mut self.left_sr),
+            "right_sl" => Some(&mut self.right_sl),
+            "right_sr" => Some(&mut self.right_sr),
+            _ => self.buttons.binding_mut(name),
+        }
+    }
+}
+
+/// Bindings of a Pro Controller, which has the buttons of both JoyCons except SL and SR.
+/// Bindings missing from a profile are the ones of the default Pro Controller mapping.
+#[derive(Serialize, JsonSchema, Debug, Clone)]
+#[serde(transparent)]
+pub struct ProControllerBindings {
+    pub buttons: ButtonBindings,
+}
+
+impl<'de> Deserialize<'de> for ProControllerBindings {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let bindings = Map::<String, Value>::deserialize(deserializer)?;
+        let mut value = serde_json::to_value(create_default_pro_controller_bindings()).unwrap();
+        if let Value::Object(defaults) = &mut value {
+            defaults.extend(bindings);
+        }
+        let buttons = serde_json::from_value(value).map_err(D::Error::custom)?;
+        Ok(ProControllerBindings { buttons })
+    }
+}
+
+/// Loads the config at `path`. Broken parts are reported and replaced with defaults.
+pub fn load_config(path: &Path) -> Result<(Config, Vec<Diagnostic>), String> {
+    let format = ConfigFormat::from_path(path)?;
+    let text = read_to_string(path).map_err(|e| format!("couldn't load file error: {}", e))?;
+    let (value, mut diagnostics) = validate_config(&text, format, Some(path));
+
+    let mut config = match value.map(|mut value| {
+        inherit_base_bindings(&mut value);
+        serde_json::from_value::<Config>(value)
+    }) {
+        Some(Ok(config)) => config,
+        Some(Err(e)) => {
+            diagnostics.push(Diagnostic {
+                severity: Severity::Error,
+                path: String::new(),
+                line: None,
+                message: format!("invalid config file error: {}, using the default", e),
+            });
+            create_default()
+        }
+        None => create_default(),
+    };
+    apply_mirror(&mut config.profile);
+    config.profiles.iter_mut().for_each(apply_mirror);
+    Ok((config, diagnostics))
+}
+
+/// Fills in the bindings that profiles in `profiles` leave out, also in their `pro_controller`,
+/// with the ones of the base profile, as its mirror leaves them. Configs that aren't one are left
+/// for `Config` to report.
+pub fn inherit_base_bindings(value: &mut Value) {
+    let base = match serde_json::from_value::<Profile>(value.clone()) {
+        Ok(mut base) => {
+            apply_mirror(&mut base);
+            serde_json::to_value(base).unwrap()
+        }
+        Err(_) => return,
+    };
+    let profiles = match value.get_mut("profiles").and_then(|p| p.as_array_mut()) {
+        Some(profiles) => profiles,
+        None => return,
+    };
+
+    for profile in profiles.iter_mut().filter_map(|p| p.as_object_mut()) {
+        for (key, binding) in base.as_object().unwrap() {
+            if !PROFILE_SETTINGS.contains(&key.as_str()) && !profile.contains_key(key) {
+                profile.insert(key.clone(), binding.clone());
+            }
+        }
+        let pro_controller = profile
+            .entry("pro_controller")
+            .or_insert_with(|| Value::Object(Map::new()));
+        if let (Value::Object(pro_controller), Value::Object(base)) =
+            (pro_controller, &base["pro_controller"])
+        {
+            for (key, binding) in base {
+                if !pro_controller.contains_key(key) {
+                    pro_controller.insert(key.clone(), binding.clone());
+                }
+            }
+        }
+    }
+}
+
+/// Loads the config at `path`, writing the default one there when there's no file yet.
+/// An existing file is only written to migrate it, with a backup, never because it's broken.
+pub fn load_config_or_default(path: &Path) -> Arc<RwLock<Box<Config>>> {
+    let exists = path.exists();
+    if exists {
+        if let Err(e) = migrate_file(path) {
+            println!("{}", e);
+        }
+    }
 
     let config = if exists {
-        match read_to_string(path) {
-            Ok(json) => match serde_json::from_str::<Config>(&json) {
-                Ok(cfg) => Some(cfg),
-                Err(e) => {
-                    println!("invalid config file error: {}", e);
-                    None
+        match load_config(path) {
+            Ok((cfg, diagnostics)) => {
+                for diagnostic in diagnostics {
+                    println!("{}", diagnostic);
                 }
-            },
+                Some(cfg)
+            }
             Err(e) => {
-                println!("couldn't load file error: {}", e);
+                println!("{}", e);
                 None
             }
         }
@@ -276,232 +1066,628 @@ pub fn load_config_or_default() -> Arc<RwLock<Box<Config>>> {
         None
     };
 
-    let config = config.unwrap_or(create_default());
+    let mut config = config.unwrap_or(create_default());
     if !exists {
-        std::fs::write(path, serde_json::to_string_pretty(&config).unwrap()).unwrap();
+        if ConfigFormat::from_path(path) == Ok(ConfigFormat::Json) {
+            config.schema = Some(SCHEMA_FILE.into());
+        }
+        match write_config(path, &config) {
+            Ok(()) => println!("[Config] wrote the default config to {}", path.display()),
+            Err(e) => println!("{}", e),
+        }
     }
+    write_schema(path, &config);
 
     Arc::new(RwLock::new(Box::new(config)))
 }
 
-fn create_default() -> Config {
+/// Writes a config in the format of `path`.
+pub fn write_config(path: &Path, config: &Config) -> Result<(), String> {
+    let format = ConfigFormat::from_path(path)?;
+    let text = format.write(&serde_json::to_value(config).unwrap())?;
+    let error = |e: std::io::Error| format!("couldn't write {} error: {}", path.display(), e);
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir).map_err(error)?;
+    }
+    std::fs::write(path, text).map_err(error)
+}
+
+pub const CONNECT_HAPTIC: &str = "connect";
+pub const STICK_SLOT_HAPTIC: &str = "stick_slot";
+pub const LOW_BATTERY_HAPTIC: &str = "warning";
+
+pub fn default_haptic_presets() -> BTreeMap<String, Vec<HapticSegment>> {
+    let segment = |frequency, amplitude, duration_ms| HapticSegment {
+        frequency,
+        amplitude,
+        duration_ms,
+        motor: Motor::Both,
+    };
+
+    let mut presets = BTreeMap::new();
+    presets.insert(CONNECT_HAPTIC.into(), vec![segment(500.0, 1.0, 500)]);
+    presets.insert(STICK_SLOT_HAPTIC.into(), vec![segment(100.0, 1.0, 30)]);
+    presets.insert("tool_changed".into(), vec![segment(160.0, 0.8, 40)]);
+    presets.insert(
+        "file_saved".into(),
+        vec![
+            segment(320.0, 0.9, 60),
+            segment(320.0, 0.0, 60),
+            segment(320.0, 0.9, 60),
+        ],
+    );
+    presets.insert(
+        "warning".into(),
+        vec![
+            segment(80.0, 1.0, 150),
+            segment(80.0, 0.0, 100),
+            segment(80.0, 1.0, 150),
+            segment(80.0, 0.0, 100),
+            segment(80.0, 1.0, 150),
+        ],
+    );
+    presets
+}
+
+pub fn create_default() -> Config {
     Config {
+        schema: None,
+        extends: None,
+        version: CONFIG_VERSION,
         show_tooltip: true,
+        haptic_presets: default_haptic_presets(),
+        player_lights: PlayerLightMode::default(),
+        profile: Profile {
+            name: "Photoshop".into(),
+            lights: None,
+            bindings: create_default_bindings(),
+            pro_controller: create_default_pro_controller_bindings(),
+            mirror: None,
+        },
+        profiles: Vec::new(),
+        controllers: Vec::new(),
+        start_profile: None,
+        gamepads: Vec::new(),
+        http_port: None,
+        osc_target: None,
+        midi_port: None,
+    }
+}
 
-        zl: KeyAction::KeyHold(KeyCombination {
-            name: "Eraser".into(),
-            key: Key::E,
-            ctrl: false,
-            alt: false,
-            shift: false,
-        }),
-        l: KeyAction::KeyHold(KeyCombination {
-            name: "Shift".into(),
-            key: Key::LeftShift,
-            ctrl: false,
-            alt: false,
-            shift: false,
-        }),
-        minus: KeyAction::KeyClick(KeyCombination {
-            name: "Save".into(),
-            key: Key::S,
-            ctrl: true,
-            alt: false,
-            shift: false,
-        }),
-        left_stick: KeyAction::None,
-        left_stick_top_left: KeyAction::KeyClick(KeyCombination {
-            name: "Bucket".into(),
-            key: Key::G,
-            ctrl: false,
-            alt: false,
-            shift: false,
-        }),
-        left_stick_top_center: KeyAction::KeyClick(KeyCombination {
-            name: "Pen".into(),
-            key: Key::P,
-            ctrl: false,
-            alt: false,
-            shift: false,
-        }),
-        left_stick_top_right: KeyAction::KeyClick(KeyCombination {
-            name: "Brush".into(),
-            key: Key::B,
-            ctrl: false,
-            alt: false,
-            shift: false,
-        }),
-        left_stick_bottom_left: KeyAction::KeyHold(KeyCombination {
-            name: "Rotate".into(),
-            key: Key::Space,
-            ctrl: false,
-            alt: false,
-            shift: true,
-        }),
-        left_stick_bottom_center: KeyAction::KeyHold(KeyCombination {
-            name: "Grab".into(),
-            key: Key::Space,
-            ctrl: false,
-            alt: false,
-            shift: false,
-        }),
-        left_stick_bottom_right: KeyAction::KeyHold(KeyCombination {
-            name: "Zoom".into(),
-            key: Key::Space,
-            ctrl: false,
-            alt: true,
-            shift: false,
-        }),
-        up: KeyAction::KeyHold(KeyCombination {
-            name: "Alt".into(),
-            key: Key::LeftAlt,
-            ctrl: false,
-            alt: false,
-            shift: false,
-        }),
-        down: KeyAction::KeyHold(KeyCombination {
-            name: "Ctrl + Shift".into(),
-            key: Key::None,
-            ctrl: true,
-            alt: false,
-            shift: true,
-        }),
-        left: KeyAction::KeyClick(KeyCombination {
-            name: "Smaller Brush".into(),
-            key: Key::Oem4OpenSquareOrCurlyBracket,
-            ctrl: false,
-            alt: false,
-            shift: false,
-        }),
-        right: KeyAction::KeyClick(KeyCombination {
-            name: "Larger Brush".into(),
-            key: Key::Oem6CloseSquareOrCurlyBracket,
-            ctrl: false,
-            alt: false,
-            shift: false,
-        }),
-        capture: KeyAction::KeyHold(KeyCombination {
-            name: "Ctrl".into(),
-            key: Key::None,
-            ctrl: true,
-            alt: false,
-            shift: false,
-        }),
+impl Default for ButtonBindings {
+    fn default() -> Self {
+        create_default_bindings().buttons
+    }
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        create_default_bindings()
+    }
+}
+
+impl Default for ProControllerBindings {
+    fn default() -> Self {
+        create_default_pro_controller_bindings()
+    }
+}
+
+fn create_default_bindings() -> Bindings {
+    Bindings {
+        buttons: ButtonBindings {
+            zl: KeyAction::KeyHold(KeyCombination {
+                name: "Eraser".into(),
+                key: Key::E,
+                modifiers: vec![],
+                haptic: None,
+            }),
+            l: KeyAction::KeyHold(KeyCombination {
+                name: "Shift".into(),
+                key: Key::LeftShift,
+                modifiers: vec![],
+                haptic: None,
+            }),
+            minus: KeyAction::KeyClick(KeyCombination {
+                name: "Save".into(),
+                key: Key::S,
+                modifiers: vec![Key::Control],
+                haptic: Some(Haptic::Preset("file_saved".into())),
+            }),
+            left_stick: KeyAction::None,
+            left_stick_top_left: KeyAction::KeyClick(KeyCombination {
+                name: "Bucket".into(),
+                key: Key::G,
+                modifiers: vec![],
+                haptic: None,
+            }),
+            left_stick_top_center: KeyAction::KeyClick(KeyCombination {
+                name: "Pen".into(),
+                key: Key::P,
+                modifiers: vec![],
+                haptic: None,
+            }),
+            left_stick_top_right: KeyAction::KeyClick(KeyCombination {
+                name: "Brush".into(),
+                key: Key::B,
+                modifiers: vec![],
+                haptic: None,
+            }),
+            left_stick_bottom_left: KeyAction::KeyHold(KeyCombination {
+                name: "Rotate".into(),
+                key: Key::Space,
+                modifiers: vec![Key::Shift],
+                haptic: None,
+            }),
+            left_stick_bottom_center: KeyAction::KeyHold(KeyCombination {
+                name: "Grab".into(),
+                key: Key::Space,
+                modifiers: vec![],
+                haptic: None,
+            }),
+            left_stick_bottom_right: KeyAction::KeyHold(KeyCombination {
+                name: "Zoom".into(),
+                key: Key::Space,
+                modifiers: vec![Key::Alt],
+                haptic: None,
+            }),
+            up: KeyAction::KeyHold(KeyCombination {
+                name: "Alt".into(),
+                key: Key::LeftAlt,
+                modifiers: vec![],
+                haptic: None,
+            }),
+            down: KeyAction::KeyHold(KeyCombination {
+                name: "Ctrl + Shift".into(),
+                key: Key::Shift,
+                modifiers: vec![Key::Control],
+                haptic: None,
+            }),
+            left: KeyAction::KeyClick(KeyCombination {
+                name: "Smaller Brush".into(),
+                key: Key::Oem4OpenSquareOrCurlyBracket,
+                modifiers: vec![],
+                haptic: None,
+            }),
+            right: KeyAction::KeyClick(KeyCombination {
+                name: "Larger Brush".into(),
+                key: Key::Oem6CloseSquareOrCurlyBracket,
+                modifiers: vec![],
+                haptic: None,
+            }),
+            capture: KeyAction::KeyHold(KeyCombination {
+                name: "Ctrl".into(),
+                key: Key::Control,
+                modifiers: vec![],
+                haptic: None,
+            }),
+
+            zr: KeyAction::KeyHold(KeyCombination {
+                name: "Eraser".into(),
+                key: Key::E,
+                modifiers: vec![],
+                haptic: None,
+            }),
+            r: KeyAction::KeyHold(KeyCombination {
+                name: "Shift".into(),
+                key: Key::LeftShift,
+                modifiers: vec![],
+                haptic: None,
+            }),
+            plus: KeyAction::KeyClick(KeyCombination {
+                name: "Save".into(),
+                key: Key::S,
+                modifiers: vec![Key::Control],
+                haptic: Some(Haptic::Preset("file_saved".into())),
+            }),
+            right_stick: KeyAction::None,
+            right_stick_top_left: KeyAction::KeyClick(KeyCombination {
+                name: "Bucket".into(),
+                key: Key::G,
+                modifiers: vec![],
+                haptic: None,
+            }),
+            right_stick_top_center: KeyAction::KeyClick(KeyCombination {
+                name: "Pen".into(),
+                key: Key::P,
+                modifiers: vec![],
+                haptic: None,
+            }),
+            right_stick_top_right: KeyAction::KeyClick(KeyCombination {
+                name: "Brush".into(),
+                key: Key::B,
+                modifiers: vec![],
+                haptic: None,
+            }),
+            right_stick_bottom_left: KeyAction::KeyHold(KeyCombination {
+                name: "Rotate".into(),
+                key: Key::Space,
+                modifiers: vec![Key::Shift],
+                haptic: None,
+            }),
+            right_stick_bottom_center: KeyAction::KeyHold(KeyCombination {
+                name: "Grab".into(),
+                key: Key::Space,
+                modifiers: vec![],
+                haptic: None,
+            }),
+            right_stick_bottom_right: KeyAction::KeyHold(KeyCombination {
+                name: "Zoom".into(),
+                key: Key::Space,
+                modifiers: vec![Key::Alt],
+                haptic: None,
+            }),
+            x: KeyAction::KeyHold(KeyCombination {
+                name: "Alt".into(),
+                key: Key::LeftAlt,
+                modifiers: vec![],
+                haptic: None,
+            }),
+            b: KeyAction::None,
+            y: KeyAction::KeyClick(KeyCombination {
+                name: "Smaller Brush".into(),
+                key: Key::Oem4OpenSquareOrCurlyBracket,
+                modifiers: vec![],
+                haptic: None,
+            }),
+            a: KeyAction::KeyClick(KeyCombination {
+                name: "Larger Brush".into(),
+                key: Key::Oem6CloseSquareOrCurlyBracket,
+                modifiers: vec![],
+                haptic: None,
+            }),
+            home: KeyAction::None,
+        },
         left_sl: KeyAction::KeyClick(KeyCombination {
             name: "Undo".into(),
             key: Key::Z,
-            ctrl: true,
-            alt: false,
-            shift: false,
+            modifiers: vec![Key::Control],
+            haptic: None,
         }),
         left_sr: KeyAction::KeyClick(KeyCombination {
             name: "Redo".into(),
             key: Key::Z,
-            ctrl: true,
-            alt: false,
-            shift: true,
+            modifiers: vec![Key::Control, Key::Shift],
+            haptic: None,
         }),
-
-        zr: KeyAction::KeyHold(KeyCombination {
-            name: "Eraser".into(),
-            key: Key::E,
-            ctrl: false,
-            alt: false,
-            shift: false,
-        }),
-        r: KeyAction::KeyHold(KeyCombination {
-            name: "Shift".into(),
-            key: Key::LeftShift,
-            ctrl: false,
-            alt: false,
-            shift: false,
-        }),
-        plus: KeyAction::KeyClick(KeyCombination {
-            name: "Save".into(),
-            key: Key::S,
-            ctrl: true,
-            alt: false,
-            shift: false,
-        }),
-        right_stick: KeyAction::None,
-        right_stick_top_left: KeyAction::KeyClick(KeyCombination {
-            name: "Bucket".into(),
-            key: Key::G,
-            ctrl: false,
-            alt: false,
-            shift: false,
-        }),
-        right_stick_top_center: KeyAction::KeyClick(KeyCombination {
-            name: "Pen".into(),
-            key: Key::P,
-            ctrl: false,
-            alt: false,
-            shift: false,
-        }),
-        right_stick_top_right: KeyAction::KeyClick(KeyCombination {
-            name: "Brush".into(),
-            key: Key::B,
-            ctrl: false,
-            alt: false,
-            shift: false,
-        }),
-        right_stick_bottom_left: KeyAction::KeyHold(KeyCombination {
-            name: "Rotate".into(),
-            key: Key::Space,
-            ctrl: false,
-            alt: false,
-            shift: true,
-        }),
-        right_stick_bottom_center: KeyAction::KeyHold(KeyCombination {
-            name: "Grab".into(),
-            key: Key::Space,
-            ctrl: false,
-            alt: false,
-            shift: false,
-        }),
-        right_stick_bottom_right: KeyAction::KeyHold(KeyCombination {
-            name: "Zoom".into(),
-            key: Key::Space,
-            ctrl: false,
-            alt: true,
-            shift: false,
-        }),
-        x: KeyAction::KeyHold(KeyCombination {
-            name: "Alt".into(),
-            key: Key::LeftAlt,
-            ctrl: false,
-            alt: false,
-            shift: false,
-        }),
-        b: KeyAction::None,
-        y: KeyAction::KeyClick(KeyCombination {
-            name: "Smaller Brush".into(),
-            key: Key::Oem4OpenSquareOrCurlyBracket,
-            ctrl: false,
-            alt: false,
-            shift: false,
-        }),
-        a: KeyAction::KeyClick(KeyCombination {
-            name: "Larger Brush".into(),
-            key: Key::Oem6CloseSquareOrCurlyBracket,
-            ctrl: false,
-            alt: false,
-            shift: false,
-        }),
-        home: KeyAction::None,
         right_sl: KeyAction::KeyClick(KeyCombination {
             name: "Undo".into(),
             key: Key::Z,
-            ctrl: true,
-            alt: false,
-            shift: false,
+            modifiers: vec![Key::Control],
+            haptic: None,
         }),
         right_sr: KeyAction::KeyClick(KeyCombination {
             name: "Redo".into(),
             key: Key::Z,
-            ctrl: true,
-            alt: false,
-            shift: true,
+            modifiers: vec![Key::Control, Key::Shift],
+            haptic: None,
         }),
     }
 }
+
+fn create_default_pro_controller_bindings() -> ProControllerBindings {
+    ProControllerBindings {
+        buttons: ButtonBindings {
+            zl: KeyAction::KeyHold(KeyCombination {
+                name: "Eraser".into(),
+                key: Key::E,
+                modifiers: vec![],
+                haptic: None,
+            }),
+            l: KeyAction::KeyHold(KeyCombination {
+                name: "Shift".into(),
+                key: Key::LeftShift,
+                modifiers: vec![],
+                haptic: None,
+            }),
+            minus: KeyAction::KeyClick(KeyCombination {
+                name: "Save".into(),
+                key: Key::S,
+                modifiers: vec![Key::Control],
+                haptic: Some(Haptic::Preset("file_saved".into())),
+            }),
+            left_stick: KeyAction::None,
+            left_stick_top_left: KeyAction::KeyClick(KeyCombination {
+                name: "Bucket".into(),
+                key: Key::G,
+                modifiers: vec![],
+                haptic: None,
+            }),
+            left_stick_top_center: KeyAction::KeyClick(KeyCombination {
+                name: "Pen".into(),
+                key: Key::P,
+                modifiers: vec![],
+                haptic: None,
+            }),
+            left_stick_top_right: KeyAction::KeyClick(KeyCombination {
+                name: "Brush".into(),
+                key: Key::B,
+                modifiers: vec![],
+                haptic: None,
+            }),
+            left_stick_bottom_left: KeyAction::KeyClick(KeyCombination {
+                name: "Lasso".into(),
+                key: Key::L,
+                modifiers: vec![],
+                haptic: None,
+            }),
+            left_stick_bottom_center: KeyAction::KeyClick(KeyCombination {
+                name: "Move".into(),
+                key: Key::V,
+                modifiers: vec![],
+                haptic: None,
+            }),
+            left_stick_bottom_right: KeyAction::KeyClick(KeyCombination {
+                name: "Eyedropper".into(),
+                key: Key::I,
+                modifiers: vec![],
+                haptic: None,
+            }),
+            up: KeyAction::KeyHold(KeyCombination {
+                name: "Alt".into(),
+                key: Key::LeftAlt,
+                modifiers: vec![],
+                haptic: None,
+            }),
+            down: KeyAction::KeyHold(KeyCombination {
+                name: "Ctrl + Shift".into(),
+                key: Key::Shift,
+                modifiers: vec![Key::Control],
+                haptic: None,
+            }),
+            left: KeyAction::KeyClick(KeyCombination {
+                name: "Smaller Brush".into(),
+                key: Key::Oem4OpenSquareOrCurlyBracket,
+                modifiers: vec![],
+                haptic: None,
+            }),
+            right: KeyAction::KeyClick(KeyCombination {
+                name: "Larger Brush".into(),
+                key: Key::Oem6CloseSquareOrCurlyBracket,
+                modifiers: vec![],
+                haptic: None,
+            }),
+            capture: KeyAction::KeyHold(KeyCombination {
+                name: "Ctrl".into(),
+                key: Key::Control,
+                modifiers: vec![],
+                haptic: None,
+            }),
+            zr: KeyAction::KeyHold(KeyCombination {
+                name: "Grab".into(),
+                key: Key::Space,
+                modifiers: vec![],
+                haptic: None,
+            }),
+            r: KeyAction::KeyHold(KeyCombination {
+                name: "Rotate".into(),
+                key: Key::R,
+                modifiers: vec![],
+                haptic: None,
+            }),
+            plus: KeyAction::KeyClick(KeyCombination {
+                name: "Save As".into(),
+                key: Key::S,
+                modifiers: vec![Key::Control, Key::Shift],
+                haptic: None,
+            }),
+            right_stick: KeyAction::None,
+            right_stick_top_left: KeyAction::KeyClick(KeyCombination {
+                name: "Previous Layer".into(),
+                key: Key::Oem4OpenSquareOrCurlyBracket,
+                modifiers: vec![Key::Alt],
+                haptic: None,
+            }),
+            right_stick_top_center: KeyAction::KeyClick(KeyCombination {
+                name: "Zoom In".into(),
+                key: Key::OemPlus,
+                modifiers: vec![Key::Control],
+                haptic: None,
+            }),
+            right_stick_top_right: KeyAction::KeyClick(KeyCombination {
+                name: "Next Layer".into(),
+                key: Key::Oem6CloseSquareOrCurlyBracket,
+                modifiers: vec![Key::Alt],
+                haptic: None,
+            }),
+            right_stick_bottom_left: KeyAction::KeyClick(KeyCombination {
+                name: "Deselect".into(),
+                key: Key::D,
+                modifiers: vec![Key::Control],
+                haptic: None,
+            }),
+            right_stick_bottom_center: KeyAction::KeyClick(KeyCombination {
+                name: "Zoom Out".into(),
+                key: Key::OemMinus,
+                modifiers: vec![Key::Control],
+                haptic: None,
+            }),
+            right_stick_bottom_right: KeyAction::KeyClick(KeyCombination {
+                name: "Fit on Screen".into(),
+                key: Key::Zero,
+                modifiers: vec![Key::Control],
+                haptic: None,
+            }),
+            a: KeyAction::KeyClick(KeyCombination {
+                name: "Undo".into(),
+                key: Key::Z,
+                modifiers: vec![Key::Control],
+                haptic: None,
+            }),
+            b: KeyAction::KeyClick(KeyCombination {
+                name: "Redo".into(),
+                key: Key::Z,
+                modifiers: vec![Key::Control, Key::Shift],
+                haptic: None,
+            }),
+            x: KeyAction::KeyClick(KeyCombination {
+                name: "Swap Colors".into(),
+                key: Key::X,
+                modifiers: vec![],
+                haptic: None,
+            }),
+            y: KeyAction::KeyClick(KeyCombination {
+                name: "New Layer".into(),
+                key: Key::N,
+                modifiers: vec![Key::Control, Key::Shift],
+                haptic: None,
+            }),
+            home: KeyAction::None,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(action: &KeyAction) -> Key {
+        match action {
+            KeyAction::KeyHold(k) | KeyAction::KeyClick(k) => k.key,
+            _ => panic!("{:?} has no key", action),
+        }
+    }
+
+    #[test]
+    fn missing_bindings_are_the_defaults_of_their_controller() {
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "zl": {"KeyClick": {"name": "Undo", "keys": "Ctrl+Z"}},
+            "pro_controller": {"zl": {"KeyClick": {"name": "Undo", "keys": "Ctrl+Z"}}},
+        }))
+        .unwrap();
+
+        let bindings = &config.profile.bindings;
+        let pro_controller = &config.profile.pro_controller.buttons;
+        assert_eq!(key(&bindings.buttons.zl), Key::Z);
+        assert_eq!(key(&pro_controller.zl), Key::Z);
+        // ZR is Eraser on JoyCons and Grab on the Pro Controller by default.
+        assert_eq!(key(&bindings.buttons.zr), Key::E);
+        assert_eq!(key(&pro_controller.zr), Key::Space);
+        assert_eq!(key(&bindings.left_sl), Key::Z);
+    }
+
+    #[test]
+    fn every_binding_can_be_found_by_name() {
+        let mut profile = create_default().profile;
+        let names = serde_json::to_value(&profile.bindings).unwrap();
+        for name in names.as_object().unwrap().keys() {
+            assert!(profile.bindings.binding_mut(name).is_some(), "{}", name);
+        }
+
+        let names = serde_json::to_value(&profile.pro_controller).unwrap();
+        assert_eq!(names.as_object().unwrap().len(), 30);
+        for name in names.as_object().unwrap().keys() {
+            let buttons = &mut profile.pro_controller.buttons;
+            assert!(buttons.binding_mut(name).is_some(), "{}", name);
+        }
+        assert!(profile
+            .pro_controller
+            .buttons
+            .binding_mut("left_sl")
+            .is_none());
+    }
+
+    #[test]
+    fn controller_settings_match_serials_and_macs_written_either_way() {
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "profiles": [{"name": "Paint"}, {"name": "Sculpt"}],
+            "controllers": [
+                {"serial_number": "98:B6:E9:12:34:56", "name": "Left hand", "profile": "Paint"},
+                {"serial_number": "XBW12345", "name": "Pro"},
+            ],
+        }))
+        .unwrap();
+        let name = |serial_number: &str| {
+            config
+                .controller_settings(serial_number)
+                .and_then(|settings| settings.name.clone())
+        };
+        assert_eq!(name("98b6e9123456").as_deref(), Some("Left hand"));
+        assert_eq!(name("98-B6-E9-12-34-56").as_deref(), Some("Left hand"));
+        assert_eq!(name("xbw12345").as_deref(), Some("Pro"));
+        assert_eq!(name("98b6e9123457"), None);
+        assert_eq!(name(""), None);
+    }
+
+    #[test]
+    fn profiles_fall_back_to_the_base_profile() {
+        let mut value = serde_json::json!({
+            "zl": {"KeyClick": {"name": "Undo", "keys": "Ctrl+Z"}},
+            "pro_controller": {"zr": {"KeyClick": {"name": "Undo", "keys": "Ctrl+Z"}}},
+            "profiles": [
+                {"name": "Paint", "l": {"KeyClick": {"name": "Brush", "keys": "B"}}},
+                {"name": "Sculpt", "zl": "None", "pro_controller": {"zl": "NextProfile"}},
+            ],
+        });
+        inherit_base_bindings(&mut value);
+        let config: Config = serde_json::from_value(value).unwrap();
+
+        let paint = &config.profiles[0];
+        assert_eq!(key(&paint.bindings.buttons.zl), Key::Z);
+        assert_eq!(key(&paint.bindings.buttons.l), Key::B);
+        assert_eq!(key(&paint.pro_controller.buttons.zr), Key::Z);
+        // Left out of the base profile too, so the default.
+        assert_eq!(key(&paint.bindings.buttons.zr), Key::E);
+
+        let sculpt = &config.profiles[1];
+        assert!(matches!(sculpt.bindings.buttons.zl, KeyAction::None));
+        assert!(matches!(
+            sculpt.pro_controller.buttons.zl,
+            KeyAction::NextProfile
+        ));
+        assert_eq!(key(&sculpt.pro_controller.buttons.zr), Key::Z);
+    }
+
+    #[test]
+    fn profiles_inherit_the_mirrored_bindings_of_the_base_profile() {
+        let path = std::env::temp_dir().join(format!(
+            "joyshop-profiles-mirror-{}.json",
+            std::process::id()
+        ));
+        let text = r#"{
+            "version": 1,
+            "mirror": "LeftToRight",
+            "zl": {"KeyClick": {"name": "Undo", "keys": "Ctrl+Z"}},
+            "profiles": [
+                {"name": "Paint"},
+                {"name": "Sculpt", "mirror": "Swap", "zr": {"KeyClick": {"name": "Brush", "keys": "B"}}}
+            ]
+        }"#;
+        std::fs::write(&path, text).unwrap();
+        let loaded = load_config(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let (config, _) = loaded.unwrap();
+        assert_eq!(key(&config.profile.bindings.buttons.zr), Key::Z);
+        let paint = &config.profiles[0].bindings.buttons;
+        assert_eq!((key(&paint.zl), key(&paint.zr)), (Key::Z, Key::Z));
+        // Its own mirror swaps what it inherited along with its own bindings.
+        let sculpt = &config.profiles[1].bindings.buttons;
+        assert_eq!((key(&sculpt.zl), key(&sculpt.zr)), (Key::B, Key::Z));
+        assert!(config.profiles[0].mirror.is_none());
+    }
+
+    #[test]
+    fn broken_bindings_of_profiles_are_the_ones_of_the_base_profile() {
+        let path =
+            std::env::temp_dir().join(format!("joyshop-profiles-{}.json", std::process::id()));
+        let text = r#"{
+            "version": 1,
+            "zl": {"KeyClick": {"name": "Undo", "keys": "Ctrl+Z"}},
+            "profiles": [{"name": "Paint", "zl": "Broken"}]
+        }"#;
+        std::fs::write(&path, text).unwrap();
+        let loaded = load_config(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let (config, diagnostics) = loaded.unwrap();
+        assert_eq!(key(&config.profiles[0].bindings.buttons.zl), Key::Z);
+        let errors = diagnostics
+            .iter()
+            .filter(|d| d.severity == Severity::Error)
+            .collect::<Vec<_>>();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0]
+            .message
+            .ends_with("using the one of the base profile"));
+    }
+}
@@ -0,0 +1,590 @@
+use crate::battery_monitor::battery_status;
+use crate::configuration::{load_config, Config, KeyAction};
+use crate::controller::{
+    controller_status, profile_actions, trigger_action, ControllerMessage, ControllerStatus,
+};
+use crate::events::{Event, EventBus};
+use crate::joyshop::send_to_controllers;
+use crate::validation::{Diagnostic, Severity};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::spawn;
+
+/// A request on the control socket, one JSON object per line like `{"command": "list_devices"}`.
+#[derive(Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case", deny_unknown_fields)]
+enum Request {
+    ListDevices,
+    /// The profile of a controller, or the one controllers start with.
+    GetProfile {
+        controller: Option<usize>,
+    },
+    /// Switches a controller, or every controller and the ones that connect later.
+    SetProfile {
+        profile: String,
+        controller: Option<usize>,
+    },
+    ReloadConfig,
+    GetBattery,
+    /// Clicks, or presses and releases, the binding with this name in the active profile.
+    Trigger {
+        action: String,
+        controller: Option<usize>,
+    },
+    /// Turns the connection into a stream of events.
+    Subscribe,
+}
+
+/// What the control socket works on.
+#[derive(Clone)]
+pub struct ControlState {
+    pub config: Arc<RwLock<Box<Config>>>,
+    /// `None` when running with a preset, which can't be reloaded.
+    pub config_path: Option<PathBuf>,
+    pub events: EventBus,
+    /// Profile controllers start with as set by `--profile` or `set_profile`, over the one of the
+    /// file, so reloading the file keeps it.
+    pub start_profile: Arc<Mutex<Option<String>>>,
+}
+
+/// Why a request failed, with the other fields of its response.
+struct Failure {
+    error: String,
+    fields: Map<String, Value>,
+}
+
+impl From<String> for Failure {
+    fn from(error: String) -> Self {
+        Failure {
+            error,
+            fields: Map::new(),
+        }
+    }
+}
+
+impl From<&str> for Failure {
+    fn from(error: &str) -> Self {
+        error.to_string().into()
+    }
+}
+
+/// Answers the requests of one client until it leaves or subscribes.
+pub fn handle_client<S: Read + Write>(stream: S, state: &ControlState) {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    loop {
+        line.clear();
+        match reader.read_line(&mut line) {
+            Ok(0) | Err(_) => return,
+            Ok(_) if line.trim().is_empty() => continue,
+            Ok(_) => {}
+        }
+
+        let (id, request) = match parse_request(&line) {
+            Ok(request) => request,
+            Err((id, e)) => {
+                if write_response(reader.get_mut(), id, Err(e.into())).is_err() {
+                    return;
+                }
+                continue;
+            }
+        };
+        if let Request::Subscribe = request {
+            let events = state.events.subscribe();
+            if write_response(reader.get_mut(), id, Ok(Map::new())).is_err() {
+                return;
+            }
+            for event in events.iter() {
+                let line = serde_json::to_string(&event).unwrap() + "\n";
+                if reader.get_mut().write_all(line.as_bytes()).is_err() {
+                    return;
+                }
+            }
+            return;
+        }
+
+        let response = handle_request(request, state);
+        if write_response(reader.get_mut(), id, response).is_err() {
+            return;
+        }
+    }
+}
+
+fn parse_request(line: &str) -> Result<(Option<Value>, Request), (Option<Value>, String)> {
+    let value = serde_json::from_str(line).map_err(|e| (None, e.to_string()))?;
+    request_from_value(value)
+}
+
+fn request_from_value(
+    mut value: Value,
+) -> Result<(Option<Value>, Request), (Option<Value>, String)> {
+    let id = value
+        .as_object_mut()
+        .and_then(|request| request.remove("id"));
+    match serde_json::from_value(value) {
+        Ok(request) => Ok((id, request)),
+        Err(e) => Err((id, e.to_string())),
+    }
+}
+
+/// Answers a request that didn't come through the control socket, where it can't `subscribe`.
+pub fn answer_request(request: Value, state: &ControlState) -> Value {
+    let (id, response) = match request_from_value(request) {
+        Ok((id, request)) => (id, handle_request(request, state)),
+        Err((id, e)) => (id, Err(e.into())),
+    };
+    response_value(id, response)
+}
+
+/// What `list_devices`, `get_profile` and `get_battery` give, in one object.
+pub fn status(state: &ControlState) -> Map<String, Value> {
+    let mut status = Map::new();
+    let requests = vec![
+        Request::ListDevices,
+        Request::GetProfile { controller: None },
+        Request::GetBattery,
+    ];
+    for request in requests {
+        if let Ok(fields) = handle_request(request, state) {
+            status.extend(fields);
+        }
+    }
+    status
+}
+
+/// Writes `{"ok": true, ...}` or `{"ok": false, "error": ...}`, with the `id` of the request.
+fn write_response(
+    stream: &mut impl Write,
+    id: Option<Value>,
+    response: Result<Map<String, Value>, Failure>,
+) -> std::io::Result<()> {
+    let line = response_value(id, response).to_string() + "\n";
+    stream.write_all(line.as_bytes())?;
+    stream.flush()
+}
+
+fn response_value(id: Option<Value>, response: Result<Map<String, Value>, Failure>) -> Value {
+    let mut line = Map::new();
+    if let Some(id) = id {
+        line.insert("id".into(), id);
+    }
+    match response {
+        Ok(fields) => {
+            line.insert("ok".into(), true.into());
+            line.extend(fields);
+        }
+        Err(failure) => {
+            line.insert("ok".into(), false.into());
+            line.insert("error".into(), failure.error.into());
+            line.extend(failure.fields);
+        }
+    }
+    Value::Object(line)
+}
+
+fn handle_request(request: Request, state: &ControlState) -> Result<Map<String, Value>, Failure> {
+    let response = match request {
+        Request::ListDevices => json!({ "controllers": controller_status() }),
+        Request::GetProfile { controller } => {
+            let config = state.config.read().unwrap();
+            let profile = match controller {
+                Some(slot) => find_controller(slot)?.profile,
+                None => config.profile(config.start_profile_index()).name.clone(),
+            };
+            let profiles = (0..config.profile_count())
+                .map(|i| config.profile(i).name.clone())
+                .collect::<Vec<_>>();
+            json!({ "profile": profile, "profiles": profiles })
+        }
+        Request::SetProfile {
+            profile,
+            controller,
+        } => {
+            let mut config = state.config.write().unwrap();
+            let index = config
+                .profile_index(&profile)
+                .ok_or_else(|| format!("unknown profile: {}", profile))?;
+            match controller {
+                Some(slot) => find_controller(slot).map(|_| ())?,
+                None => {
+                    config.start_profile = Some(profile.clone());
+                    *state.start_profile.lock().unwrap() = Some(profile);
+                }
+            }
+            send_to_controllers(|| ControllerMessage::SwitchProfile {
+                slot: controller,
+                index,
+            });
+            json!({})
+        }
+        Request::ReloadConfig => {
+            let path = state
+                .config_path
+                .as_ref()
+                .ok_or("joyshop is running with a preset, there's no file to reload")?;
+            let (mut config, mut diagnostics) = load_config(path)?;
+            if let Some(name) = state.start_profile.lock().unwrap().clone() {
+                match config.profile_index(&name) {
+                    Some(_) => config.start_profile = Some(name),
+                    None => diagnostics.push(Diagnostic {
+                        severity: Severity::Warning,
+                        path: String::new(),
+                        line: None,
+                        message: format!(
+                            "there's no profile `{}` anymore, starting with the one of the file",
+                            name
+                        ),
+                    }),
+                }
+            }
+            for diagnostic in diagnostics.iter() {
+                println!("{}", diagnostic);
+            }
+            let has_errors = diagnostics
+                .iter()
+                .any(|diagnostic| diagnostic.severity == Severity::Error);
+            let diagnostics = diagnostics
+                .iter()
+                .map(|diagnostic| diagnostic.to_string())
+                .collect::<Vec<_>>();
+            // A typo shouldn't swap the bindings in use for defaults.
+            if has_errors {
+                let mut fields = Map::new();
+                fields.insert("diagnostics".into(), diagnostics.into());
+                return Err(Failure {
+                    error: format!("{} has errors, keeping the config in use", path.display()),
+                    fields,
+                });
+            }
+            **state.config.write().unwrap() = config;
+            state.events.publish(Event::ConfigReloaded {
+                path: path.display().to_string(),
+            });
+            json!({ "diagnostics": diagnostics })
+        }
+        Request::GetBattery => {
+            let batteries = battery_status()
+                .iter()
+                .map(|status| {
+                    json!({
+                        "serial_number": status.serial_number,
+                        "level": status.level,
+                        "is_charging": status.is_charging,
+                        "remaining_minutes": status.estimated_remaining().map(|r| r.as_secs() / 60),
+                    })
+                })
+                .collect::<Vec<_>>();
+            json!({ "batteries": batteries })
+        }
+        Request::Trigger { action, controller } => {
+            let config = state.config.read().unwrap();
+            let index = match controller {
+                Some(slot) => {
+                    let profile = find_controller(slot)?.profile;
+                    config.profile_index(&profile).unwrap_or(0)
+                }
+                None => match controller_status().first() {
+                    Some(status) => config.profile_index(&status.profile).unwrap_or(0),
+                    None => config.start_profile_index(),
+                },
+            };
+            let profile = config.profile(index);
+            let found = profile_actions(profile)
+                .into_iter()
+                .find(|found| match found {
+                    KeyAction::KeyHold(k) | KeyAction::KeyClick(k) => k.name == action,
+                    _ => false,
+                })
+                .ok_or_else(|| format!("no binding called {} in {}", action, profile.name))?;
+            trigger_action(&config, found, &state.events);
+            json!({})
+        }
+        // Only the control socket can stream events, on the connection of the request.
+        Request::Subscribe => return Err("subscribe isn't a request here".into()),
+    };
+    match response {
+        Value::Object(fields) => Ok(fields),
+        response => Err(format!("{} isn't a response", response).into()),
+    }
+}
+
+fn find_controller(slot: usize) -> Result<ControllerStatus, String> {
+    controller_status()
+        .into_iter()
+        .find(|status| status.slot == slot)
+        .ok_or_else(|| format!("no controller in slot {}", slot))
+}
+
+/// Where the control socket is: `$XDG_RUNTIME_DIR/joyshop.sock`, or in a directory of the user
+/// in the temp directory when there's no runtime directory.
+#[cfg(unix)]
+pub fn control_path() -> PathBuf {
+    control_dir().join("joyshop.sock")
+}
+
+/// A directory only the user can open, so the socket is never open to others, not even
+/// between binding it and setting its permissions.
+#[cfg(unix)]
+fn control_dir() -> PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR").filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => std::env::temp_dir().join(format!("joyshop-{}", unsafe { libc::getuid() })),
+    }
+}
+
+/// Makes the directory of the socket when it's missing, and checks that it's only the user's.
+#[cfg(unix)]
+fn create_control_dir(dir: &Path) -> Result<(), String> {
+    use std::fs::{symlink_metadata, DirBuilder};
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+
+    if !dir.exists() {
+        DirBuilder::new()
+            .mode(0o700)
+            .create(dir)
+            .map_err(|e| format!("couldn't create {} error: {}", dir.display(), e))?;
+    }
+    let metadata = symlink_metadata(dir).map_err(|e| format!("{} error: {}", dir.display(), e))?;
+    if !metadata.is_dir()
+        || metadata.uid() != unsafe { libc::getuid() }
+        || metadata.mode() & 0o077 != 0
+    {
+        return Err(format!(
+            "{} isn't a directory only this user can open",
+            dir.display()
+        ));
+    }
+    Ok(())
+}
+
+/// The named pipe `\\.\pipe\joyshop`.
+#[cfg(windows)]
+pub fn control_path() -> PathBuf {
+    PathBuf::from(r"\\.\pipe\joyshop")
+}
+
+/// Serves the control socket, each client on a thread of its own.
+#[cfg(unix)]
+pub fn serve_control(state: ControlState) {
+    use std::fs::remove_file;
+    use std::os::unix::net::{UnixListener, UnixStream};
+
+    if let Err(e) = create_control_dir(&control_dir()) {
+        println!("{}", e);
+        return;
+    }
+    let path = control_path();
+    if path.exists() {
+        if UnixStream::connect(&path).is_ok() {
+            println!("[Control] {} is used by another joyshop", path.display());
+            return;
+        }
+        // Left by a joyshop that didn't exit cleanly.
+        let _ = remove_file(&path);
+    }
+    let listener = match UnixListener::bind(&path) {
+        Ok(listener) => listener,
+        Err(e) => {
+            println!("couldn't open {} error: {}", path.display(), e);
+            return;
+        }
+    };
+    println!("[Control] {}", path.display());
+    for stream in listener.incoming().flatten() {
+        let state = state.clone();
+        spawn(move || handle_client(stream, &state));
+    }
+}
+
+/// Serves the control pipe, each client on a thread of its own.
+/// The next instance of the pipe is made before a client is handled, so there's always one to connect to.
+#[cfg(windows)]
+pub fn serve_control(state: ControlState) {
+    use crate::named_pipe::PipeStream;
+
+    let path = control_path();
+    let mut pipe = match PipeStream::create(&path, true) {
+        Ok(pipe) => pipe,
+        Err(e) => {
+            println!("couldn't open {} error: {}", path.display(), e);
+            return;
+        }
+    };
+
+    println!("[Control] {}", path.display());
+    loop {
+        if let Err(e) = pipe.connect() {
+            println!("{} error: {}", path.display(), e);
+            return;
+        }
+        let next = match PipeStream::create(&path, false) {
+            Ok(next) => next,
+            Err(e) => {
+                println!("couldn't open {} error: {}", path.display(), e);
+                return;
+            }
+        };
+        let client = std::mem::replace(&mut pipe, next);
+        let state = state.clone();
+        spawn(move || handle_client(client, &state));
+    }
+}
+
+/// Sends a request to a running joyshop and prints the response, or every event after `subscribe`.
+/// A request that isn't JSON is the name of a command, like `list_devices`.
+/// Returns whether the request succeeded.
+pub fn send_request(request: &str) -> Result<bool, String> {
+    let request = match serde_json::from_str::<Value>(request) {
+        Ok(request) => request,
+        Err(_) => json!({ "command": request.trim() }),
+    };
+    let subscribe = request.get("command").and_then(Value::as_str) == Some("subscribe");
+
+    let path = control_path();
+    let error = |e: std::io::Error| format!("couldn't connect to {} error: {}", path.display(), e);
+    let mut reader = BufReader::new(connect(&path).map_err(error)?);
+    let line = request.to_string() + "\n";
+    reader.get_mut().write_all(line.as_bytes()).map_err(error)?;
+
+    let mut ok = false;
+    for line in reader.lines() {
+        let line = line.map_err(error)?;
+        println!("{}", line);
+        if !subscribe {
+            ok = serde_json::from_str::<Value>(&line)
+                .ok()
+                .and_then(|response| response.get("ok").and_then(Value::as_bool))
+                .unwrap_or(false);
+            break;
+        }
+    }
+    Ok(ok)
+}
+
+#[cfg(unix)]
+fn connect(path: &Path) -> std::io::Result<impl Read + Write> {
+    std::os::unix::net::UnixStream::connect(path)
+}
+
+#[cfg(windows)]
+fn connect(path: &Path) -> std::io::Result<impl Read + Write> {
+    std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(state: &ControlState, request: Value) -> Value {
+        answer_request(request, state)
+    }
+
+    #[test]
+    fn reloading_keeps_the_profile_set_at_runtime() {
+        let path =
+            std::env::temp_dir().join(format!("joyshop-control-{}.json", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"{"version": 1, "profiles": [{"name": "Paint"}, {"name": "Sculpt"}]}"#,
+        )
+        .unwrap();
+        let (config, _) = load_config(&path).unwrap();
+        let state = ControlState {
+            config: Arc::new(RwLock::new(Box::new(config))),
+            config_path: Some(path.clone()),
+            events: EventBus::new(),
+            start_profile: Arc::new(Mutex::new(None)),
+        };
+
+        let set = request(
+            &state,
+            json!({"id": 1, "command": "set_profile", "profile": "Sculpt"}),
+        );
+        let reloaded = request(&state, json!({"command": "reload_config"}));
+        let profile = request(&state, json!({"command": "get_profile"}));
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(set, json!({"id": 1, "ok": true}));
+        assert_eq!(reloaded["ok"], true);
+        assert_eq!(profile["profile"], "Sculpt");
+    }
+
+    #[test]
+    fn reloading_a_file_with_errors_keeps_the_config_in_use() {
+        let path = std::env::temp_dir().join(format!(
+            "joyshop-control-invalid-{}.json",
+            std::process::id()
+        ));
+        std::fs::write(
+            &path,
+            r#"{"version": 1, "profiles": [{"name": "Paint"}, {"name": "Sculpt"}]}"#,
+        )
+        .unwrap();
+        let (config, _) = load_config(&path).unwrap();
+        let state = ControlState {
+            config: Arc::new(RwLock::new(Box::new(config))),
+            config_path: Some(path.clone()),
+            events: EventBus::new(),
+            start_profile: Arc::new(Mutex::new(None)),
+        };
+
+        std::fs::write(&path, r#"{"version": 1, "profiles": [{"name": "Paint""#).unwrap();
+        let reloaded = request(&state, json!({"command": "reload_config"}));
+        let profile = request(&state, json!({"command": "get_profile"}));
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(reloaded["ok"], false);
+        assert!(reloaded["error"]
+            .as_str()
+            .unwrap()
+            .ends_with("has errors, keeping the config in use"));
+        assert!(!reloaded["diagnostics"].as_array().unwrap().is_empty());
+        assert_eq!(profile["profiles"], json!(["Default", "Paint", "Sculpt"]));
+    }
+
+    #[test]
+    fn subscribe_is_refused_outside_the_control_socket() {
+        let state = ControlState {
+            config: Arc::new(RwLock::new(
+                Box::new(crate::configuration::create_default()),
+            )),
+            config_path: None,
+            events: EventBus::new(),
+            start_profile: Arc::new(Mutex::new(None)),
+        };
+        assert_eq!(
+            request(&state, json!({"id": 2, "command": "subscribe"})),
+            json!({"id": 2, "ok": false, "error": "subscribe isn't a request here"})
+        );
+        assert_eq!(
+            request(&state, json!({"command": "reload_config"}))["error"],
+            "joyshop is running with a preset, there's no file to reload"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn the_socket_directory_is_only_the_users() {
+        use std::fs::{metadata, remove_dir, set_permissions, Permissions};
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("joyshop-control-dir-{}", std::process::id()));
+        let created = create_control_dir(&dir);
+        let mode = metadata(&dir).unwrap().permissions().mode() & 0o777;
+        set_permissions(&dir, Permissions::from_mode(0o755)).unwrap();
+        let opened = create_control_dir(&dir);
+        remove_dir(&dir).unwrap();
+
+        assert_eq!(created, Ok(()));
+        assert_eq!(mode, 0o700);
+        assert!(opened
+            .unwrap_err()
+            .ends_with("isn't a directory only this user can open"));
+    }
+}
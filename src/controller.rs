@@ -0,0 +1,688 @@
+use crate::battery_monitor::{battery_status, format_battery_status};
+use crate::configuration::{
+    Bindings, ButtonBindings, Config, HapticSegment, KeyAction, Profile, STICK_SLOT_HAPTIC,
+};
+use crate::events::{ControllerInfo, Event, EventBus, Source};
+use crate::haptics::{action_haptic, device_segments, resolve_haptic, resolve_preset, Overlap};
+use crate::input_recognizer::{
+    is_button_down, is_button_press, is_button_up, recognize_stick_slot, Side, SideInput,
+};
+use crate::key_sender::send_ev;
+use crate::player_lights::{get_light_states, LightStates};
+use crate::{midi, osc};
+use crossbeam_channel::{Receiver, Sender};
+use joycon_rs::joycon::Buttons;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+/// The connected controllers by slot, lowest first.
+static CONTROLLER_SLOTS: Lazy<Mutex<BTreeMap<usize, ControllerStatus>>> =
+    Lazy::new(|| Mutex::new(BTreeMap::new()));
+
+/// What a controller is, for the control socket.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ControllerStatus {
+    pub slot: usize,
+    pub name: Option<String>,
+    pub profile: String,
+    /// Serial numbers of its devices.
+    pub devices: Vec<String>,
+}
+
+pub fn controller_status() -> Vec<ControllerStatus> {
+    CONTROLLER_SLOTS.lock().unwrap().values().cloned().collect()
+}
+
+/// Whether input is printed instead of sent as keys.
+static MONITOR: AtomicBool = AtomicBool::new(false);
+
+pub fn enable_monitor() {
+    MONITOR.store(true, Ordering::Relaxed);
+}
+
+pub enum ControllerMessage {
+    Connected {
+        device_id: usize,
+        serial_number: String,
+        sides: &'static [Side],
+        commands: Sender<DeviceCommand>,
+    },
+    Input {
+        device_id: usize,
+        inputs: Vec<(Side, SideInput)>,
+    },
+    Disconnected {
+        device_id: usize,
+    },
+    /// Switches the controller in `slot`, or every controller, to the profile at `index`.
+    SwitchProfile {
+        slot: Option<usize>,
+        index: usize,
+    },
+}
+
+pub enum DeviceCommand {
+    Haptic(Vec<HapticSegment>, Overlap),
+    Lights(LightStates),
+}
+
+struct Device {
+    serial_number: String,
+    sides: &'static [Side],
+    commands: Sender<DeviceCommand>,
+    lights: Option<LightStates>,
+}
+
+/// One or two devices acting as a single controller, e.g. a pair of JoyCons.
+/// Bindings of a side are only evaluated while a device provides that side.
+struct Controller {
+    /// Name from the settings of the first connected device that has some.
+    name: Option<String>,
+    has_settings: bool,
+    slot: usize,
+    profile_index: usize,
+    devices: BTreeMap<usize, Device>,
+    inputs: BTreeMap<Side, SideInput>,
+    sticks: BTreeMap<Side, Option<usize>>,
+    events: EventBus,
+}
+
+pub fn handle_controller(
+    messages: Receiver<ControllerMessage>,
+    config: Arc<RwLock<Box<Config>>>,
+    events: EventBus,
+) {
+    let profile_index = match config.read() {
+        Ok(config) => config.start_profile_index(),
+        Err(_) => 0,
+    };
+    let mut controller = Controller {
+        name: None,
+        has_settings: false,
+        slot: acquire_slot(),
+        profile_index,
+        devices: BTreeMap::new(),
+        inputs: BTreeMap::new(),
+        sticks: BTreeMap::new(),
+        events,
+    };
+
+    for message in messages.iter() {
+        let config = match config.read() {
+            Ok(v) => v,
+            Err(_) => continue,
+        };
+
+        match message {
+            ControllerMessage::Connected {
+                device_id,
+                serial_number,
+                sides,
+                commands,
+            } => {
+                controller.apply_settings(&config, &serial_number);
+                controller.events.publish(Event::DeviceConnected {
+                    controller: controller.info(),
+                    serial_number: serial_number.clone(),
+                });
+                let device = Device {
+                    serial_number,
+                    sides,
+                    commands,
+                    lights: None,
+                };
+                controller.devices.insert(device_id, device);
+                controller.update_status(&config);
+            }
+            ControllerMessage::Input { device_id, inputs } => {
+                controller.handle_input(&config, inputs);
+                controller.update_lights(&config, device_id);
+            }
+            ControllerMessage::Disconnected { device_id } => {
+                controller.disconnect(&config, device_id);
+                controller.update_status(&config);
+            }
+            ControllerMessage::SwitchProfile { slot, index } => {
+                if slot.is_none() || slot == Some(controller.slot) {
+                    controller.switch_profile(&config, index);
+                }
+            }
+        }
+    }
+
+    release_slot(controller.slot);
+}
+
+/// Presses and releases an action that no controller fired, e.g. one from the control socket.
+pub fn trigger_action(config: &Config, action: &KeyAction, events: &EventBus) {
+    if MONITOR.load(Ordering::Relaxed) {
+        println!("[Monitor] remote {:?}", action);
+        return;
+    }
+    for is_down in [true, false] {
+        send_ev(action, is_down);
+        for result in [
+            osc::send_action(config, action, is_down),
+            midi::send_action(config, action, is_down),
+        ] {
+            if let Err(e) = result {
+                events.error(e);
+            }
+        }
+    }
+    for is_down in [true, false] {
+        let (controller, source, action) = (None, Source::Remote, action.clone());
+        events.publish(if is_down {
+            Event::ActionFired {
+                controller,
+                source,
+                action,
+            }
+        } else {
+            Event::ActionReleased {
+                controller,
+                source,
+                action,
+            }
+        });
+    }
+}
+
+fn acquire_slot() -> usize {
+    let mut slots = CONTROLLER_SLOTS.lock().unwrap();
+    let slot = (0..).find(|slot| !slots.contains_key(slot)).unwrap();
+    let status = ControllerStatus {
+        slot,
+        ..ControllerStatus::default()
+    };
+    slots.insert(slot, status);
+    slot
+}
+
+fn release_slot(slot: usize) {
+    CONTROLLER_SLOTS.lock().unwrap().remove(&slot);
+}
+
+impl Controller {
+    fn apply_settings(&mut self, config: &Config, serial_number: &str) {
+        if self.has_settings {
+            return;
+        }
+        let settings = match config.controller_settings(serial_number) {
+            Some(settings) => settings,
+            None => return,
+        };
+
+        self.has_settings = true;
+        self.name = settings.name.clone();
+        if let Some(profile) = &settings.profile {
+            match config.profile_index(profile) {
+                Some(index) => self.profile_index = index,
+                None => println!("unknown profile: {}", profile),
+            }
+        }
+    }
+
+    fn send_ev(&self, config: &Config, action: &KeyAction, is_down: bool, source: Source) {
+        if MONITOR.load(Ordering::Relaxed) {
+            if !matches!(action, KeyAction::None) {
+                let state = if is_down { "down" } else { "up" };
+                println!("[Monitor] {} {:?} {}", self.label(), action, state);
+            }
+            return;
+        }
+        send_ev(action, is_down);
+        for result in [
+            osc::send_action(config, action, is_down),
+            midi::send_action(config, action, is_down),
+        ] {
+            if let Err(e) = result {
+                self.events.error(e);
+            }
+        }
+        if matches!(action, KeyAction::None) {
+            return;
+        }
+
+        let controller = Some(self.info());
+        let action = action.clone();
+        self.events.publish(if is_down {
+            Event::ActionFired {
+                controller,
+                source,
+                action,
+            }
+        } else {
+            Event::ActionReleased {
+                controller,
+                source,
+                action,
+            }
+        });
+    }
+
+    /// Streams the input of `OscStream` and `MidiCcStream` actions, for each report their button
+    /// is held.
+    fn stream_input(&self, config: &Config, action: &KeyAction, input: &SideInput) {
+        if MONITOR.load(Ordering::Relaxed) {
+            return;
+        }
+        let result = match action {
+            KeyAction::OscStream(stream) => osc::send_stream(config, stream, input),
+            KeyAction::MidiCcStream(stream) => midi::send_stream(config, stream, input),
+            _ => Ok(()),
+        };
+        if let Err(e) = result {
+            self.events.error(e);
+        }
+    }
+
+    fn info(&self) -> ControllerInfo {
+        ControllerInfo {
+            slot: self.slot,
+            name: self.name.clone(),
+        }
+    }
+
+    fn label(&self) -> String {
+        self.info().label()
+    }
+
+    fn monitor_input(&self, side: Side, last_input: &SideInput, input: &SideInput) {
+        for button in input.buttons.iter() {
+            if !last_input.buttons.contains(button) {
+                println!("[Monitor] {} {:?} {:?} down", self.label(), side, button);
+            }
+        }
+        for button in last_input.buttons.iter() {
+            if !input.buttons.contains(button) {
+                println!("[Monitor] {} {:?} {:?} up", self.label(), side, button);
+            }
+        }
+    }
+
+    fn handle_input(&mut self, config: &Config, inputs: Vec<(Side, SideInput)>) {
+        let profile = config.profile(self.profile_index);
+        let buttons = self.button_bindings(profile);
+        let mut pressed = Vec::new();
+
+        for (side, input) in inputs {
+            // The first input of a side is the state it starts from.
+            let last_input = match self.inputs.insert(side, input.clone()) {
+                Some(last_input) => last_input,
+                None => {
+                    let stick = recognize_stick_slot(6, 0, None, &input.stick);
+                    self.sticks.insert(side, stick);
+                    continue;
+                }
+            };
+
+            if MONITOR.load(Ordering::Relaxed) {
+                self.monitor_input(side, &last_input, &input);
+            }
+
+            for &(button_side, button, action) in buttons.iter() {
+                if button_side != side {
+                    continue;
+                }
+
+                let source = Source::Button(side, button);
+                if is_button_down(&last_input, &input, button) {
+                    self.send_ev(config, action, true, source);
+                    if let Some(segments) =
+                        action_haptic(action).and_then(|h| resolve_haptic(config, h))
+                    {
+                        self.play_haptic(side, segments);
+                    }
+                    pressed.push(action);
+                }
+
+                if is_button_up(&last_input, &input, button) {
+                    self.send_ev(config, action, false, source);
+                }
+                if is_button_press(&input, button) {
+                    self.stream_input(config, action, &input);
+                }
+            }
+
+            let last_stick = self.sticks.get(&side).copied().flatten();
+            let stick = recognize_stick_slot(6, 0, last_stick, &input.stick);
+            self.sticks.insert(side, stick);
+            let slots = self.stick_bindings(profile, side);
+            if let Some(i) = stick.filter(|&i| i < slots.len()) {
+                self.stream_input(config, slots[i], &input);
+            }
+            if stick == last_stick {
+                continue;
+            }
+            if MONITOR.load(Ordering::Relaxed) {
+                println!(
+                    "[Monitor] {} {:?} stick slot {:?}",
+                    self.label(),
+                    side,
+                    stick
+                );
+            }
+
+            if let Some(i) = last_stick.filter(|&i| i < slots.len()) {
+                self.send_ev(config, slots[i], false, Source::StickSlot(side, i));
+            }
+
+            if let Some(i) = stick.filter(|&i| i < slots.len()) {
+                let action = slots[i];
+                self.send_ev(config, action, true, Source::StickSlot(side, i));
+                let haptic = match action_haptic(action) {
+                    Some(haptic) => resolve_haptic(config, haptic),
+                    None => resolve_preset(config, STICK_SLOT_HAPTIC),
+                };
+                if let Some(segments) = haptic {
+                    self.play_haptic(side, segments);
+                }
+                pressed.push(action);
+            }
+        }
+
+        let next_profile = pressed
+            .iter()
+            .rev()
+            .find_map(|action| profile_switch(config, self.profile_index, action));
+        if let Some(next_profile) = next_profile {
+            self.switch_profile(config, next_profile);
+        }
+
+        if pressed
+            .iter()
+            .any(|action| matches!(action, KeyAction::ShowBatteryStatus))
+        {
+            let text = battery_status()
+                .iter()
+                .map(|status| {
+                    let label = config
+                        .controller_settings(&status.serial_number)
+                        .and_then(|settings| settings.name.as_deref())
+                        .unwrap_or(&status.serial_number);
+                    format!("{}: {}", label, format_battery_status(status))
+                })
+                .collect::<Vec<_>>()
+                .join(" / ");
+            self.events.publish(Event::BatteryReport {
+                controller: self.info(),
+                text,
+            });
+        }
+    }
+
+    fn switch_profile(&mut self, config: &Config, index: usize) {
+        for side in self.inputs.keys() {
+            self.release_held(config, *side);
+        }
+        self.profile_index = index;
+        self.update_status(config);
+        self.events.publish(Event::ProfileSwitched {
+            controller: self.info(),
+            profile: config.profile(self.profile_index).name.clone(),
+        });
+    }
+
+    fn update_status(&self, config: &Config) {
+        let status = ControllerStatus {
+            slot: self.slot,
+            name: self.name.clone(),
+            profile: config.profile(self.profile_index).name.clone(),
+            devices: self
+                .devices
+                .values()
+                .map(|device| device.serial_number.clone())
+                .collect(),
+        };
+        CONTROLLER_SLOTS.lock().unwrap().insert(self.slot, status);
+    }
+
+    fn update_lights(&mut self, config: &Config, device_id: usize) {
+        let device = match self.devices.get_mut(&device_id) {
+            Some(device) => device,
+            None => return,
+        };
+        let inputs = &self.inputs;
+        let battery = device.sides.first().and_then(|side| inputs.get(side));
+        let battery = match battery.and_then(|input| input.battery) {
+            Some(battery) => battery,
+            None => return,
+        };
+
+        let light_states = get_light_states(config, battery, self.profile_index, self.slot);
+        if device.lights != Some(light_states) {
+            device.lights = Some(light_states);
+            let _ = device.commands.send(DeviceCommand::Lights(light_states));
+        }
+    }
+
+    fn disconnect(&mut self, config: &Config, device_id: usize) {
+        let (sides, serial_number) = match self.devices.get(&device_id) {
+            Some(device) => (device.sides, device.serial_number.clone()),
+            None => return,
+        };
+
+        // Released while the device is still known, so a Pro Controller uses its own table.
+        for side in sides {
+            self.release_held(config, *side);
+        }
+
+        self.devices.remove(&device_id);
+        for side in sides {
+            self.inputs.remove(side);
+            self.sticks.remove(side);
+        }
+        self.events.publish(Event::DeviceDisconnected {
+            controller: self.info(),
+            serial_number,
+        });
+    }
+
+    fn play_haptic(&self, side: Side, segments: Vec<HapticSegment>) {
+        for device in self.devices.values() {
+            if let Some(segments) = device_segments(&segments, device.sides, side) {
+                let _ = device
+                    .commands
+                    .send(DeviceCommand::Haptic(segments, Overlap::Preempt));
+            }
+        }
+    }
+
+    /// A Pro Controller provides both sides from a single device.
+    fn is_pro_controller(&self) -> bool {
+        self.devices.values().any(|d| d.sides.len() == 2)
+    }
+
+    fn button_bindings<'a>(&self, profile: &'a Profile) -> Vec<(Side, Buttons, &'a KeyAction)> {
+        if self.is_pro_controller() {
+            button_bindings(&profile.pro_controller.buttons).to_vec()
+        } else {
+            let mut bindings = button_bindings(&profile.bindings.buttons).to_vec();
+            bindings.extend_from_slice(&sl_sr_bindings(&profile.bindings));
+            bindings
+        }
+    }
+
+    fn stick_bindings<'a>(&self, profile: &'a Profile, side: Side) -> [&'a KeyAction; 6] {
+        if self.is_pro_controller() {
+            stick_bindings(&profile.pro_controller.buttons, side)
+        } else {
+            stick_bindings(&profile.bindings.buttons, side)
+        }
+    }
+
+    /// Releases what a side holds, so switching profiles or disconnecting doesn't leave keys
+    /// stuck down.
+    fn release_held(&self, config: &Config, side: Side) {
+        let input = match self.inputs.get(&side) {
+            Some(input) => input,
+            None => return,
+        };
+
+        let profile = config.profile(self.profile_index);
+        for &(button_side, button, action) in self.button_bindings(profile).iter() {
+            if button_side == side && is_button_press(input, button) {
+                self.send_ev(config, action, false, Source::Button(side, button));
+            }
+        }
+
+        let stick = self.sticks.get(&side).copied().flatten();
+        let slots = self.stick_bindings(profile, side);
+        if let Some(i) = stick.filter(|&i| i < slots.len()) {
+            self.send_ev(config, slots[i], false, Source::StickSlot(side, i));
+        }
+    }
+}
+
+/// Every binding of a profile, JoyCon ones first.
+pub fn profile_actions(profile: &Profile) -> Vec<&KeyAction> {
+    let bindings = &profile.bindings;
+    let pro_controller = &profile.pro_controller.buttons;
+    let mut actions = button_bindings(&bindings.buttons)
+        .iter()
+        .chain(sl_sr_bindings(bindings).iter())
+        .chain(button_bindings(pro_controller).iter())
+        .map(|(_, _, action)| *action)
+        .collect::<Vec<_>>();
+    for side in [Side::Left, Side::Right].iter().copied() {
+        actions.extend_from_slice(&stick_bindings(&bindings.buttons, side));
+        actions.extend_from_slice(&stick_bindings(pro_controller, side));
+    }
+    actions
+}
+
+/// The buttons of both JoyCons except SL and SR, which are all the buttons of a Pro Controller.
+fn button_bindings(bindings: &ButtonBindings) -> [(Side, Buttons, &KeyAction); 18] {
+    [
+        (Side::Left, Buttons::ZL, &bindings.zl),
+        (Side::Left, Buttons::L, &bindings.l),
+        (Side::Left, Buttons::Minus, &bindings.minus),
+        (Side::Left, Buttons::LStick, &bindings.left_stick),
+        (Side::Left, Buttons::Up, &bindings.up),
+        (Side::Left, Buttons::Down, &bindings.down),
+        (Side::Left, Buttons::Left, &bindings.left),
+        (Side::Left, Buttons::Right, &bindings.right),
+        (Side::Left, Buttons::Capture, &bindings.capture),
+        (Side::Right, Buttons::ZR, &bindings.zr),
+        (Side::Right, Buttons::R, &bindings.r),
+        (Side::Right, Buttons::Plus, &bindings.plus),
+        (Side::Right, Buttons::RStick, &bindings.right_stick),
+        (Side::Right, Buttons::A, &bindings.a),
+        (Side::Right, Buttons::B, &bindings.b),
+        (Side::Right, Buttons::X, &bindings.x),
+        (Side::Right, Buttons::Y, &bindings.y),
+        (Side::Right, Buttons::Home, &bindings.home),
+    ]
+}
+
+/// The SL and SR buttons on the rail of each JoyCon.
+fn sl_sr_bindings(bindings: &Bindings) -> [(Side, Buttons, &KeyAction); 4] {
+    [
+        (Side::Left, Buttons::SL, &bindings.left_sl),
+        (Side::Left, Buttons::SR, &bindings.left_sr),
+        (Side::Right, Buttons::SL, &bindings.right_sl),
+        (Side::Right, Buttons::SR, &bindings.right_sr),
+    ]
+}
+
+/// Actions of the stick slots, in the order `recognize_stick_slot` numbers them.
+fn stick_bindings(bindings: &ButtonBindings, side: Side) -> [&KeyAction; 6] {
+    match side {
+        Side::Left => [
+            &bindings.left_stick_top_right,
+            &bindings.left_stick_top_center,
+            &bindings.left_stick_top_left,
+            &bindings.left_stick_bottom_left,
+            &bindings.left_stick_bottom_center,
+            &bindings.left_stick_bottom_right,
+        ],
+        Side::Right => [
+            &bindings.right_stick_top_right,
+            &bindings.right_stick_top_center,
+            &bindings.right_stick_top_left,
+            &bindings.right_stick_bottom_left,
+            &bindings.right_stick_bottom_center,
+            &bindings.right_stick_bottom_right,
+        ],
+    }
+}
+
+fn profile_switch(config: &Config, profile_index: usize, action: &KeyAction) -> Option<usize> {
+    match action {
+        KeyAction::NextProfile => Some((profile_index + 1) % config.profile_count()),
+        KeyAction::SwitchProfile(name) => {
+            let index = config.profile_index(name);
+            if index.is_none() {
+                println!("unknown profile: {}", name);
+            }
+            index
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> Config {
+        serde_json::from_value(serde_json::json!({
+            "profiles": [{"name": "Paint"}, {"name": "Sculpt"}],
+            "controllers": [
+                {"serial_number": "left", "name": "Left hand", "profile": "Paint"},
+                {"serial_number": "right", "name": "Right hand", "profile": "Sculpt"},
+                {"serial_number": "lost", "name": "Lost", "profile": "Gone"},
+            ],
+        }))
+        .unwrap()
+    }
+
+    /// A controller outside `CONTROLLER_SLOTS`, so tests don't share it.
+    fn controller() -> Controller {
+        Controller {
+            name: None,
+            has_settings: false,
+            slot: usize::MAX,
+            profile_index: 0,
+            devices: BTreeMap::new(),
+            inputs: BTreeMap::new(),
+            sticks: BTreeMap::new(),
+            events: EventBus::new(),
+        }
+    }
+
+    #[test]
+    fn unknown_controllers_keep_the_start_profile() {
+        let mut controller = controller();
+        controller.apply_settings(&config(), "unknown");
+        assert_eq!(controller.name, None);
+        assert_eq!(controller.profile_index, 0);
+        assert!(!controller.has_settings);
+    }
+
+    #[test]
+    fn the_first_joycon_with_settings_names_the_pair() {
+        let config = config();
+        let mut controller = controller();
+        controller.apply_settings(&config, "unknown");
+        controller.apply_settings(&config, "right");
+        controller.apply_settings(&config, "left");
+        assert_eq!(controller.name.as_deref(), Some("Right hand"));
+        assert_eq!(
+            controller.profile_index,
+            config.profile_index("Sculpt").unwrap()
+        );
+    }
+
+    #[test]
+    fn unknown_profiles_of_settings_are_left_out() {
+        let mut controller = controller();
+        controller.apply_settings(&config(), "lost");
+        assert_eq!(controller.name.as_deref(), Some("Lost"));
+        assert_eq!(controller.profile_index, 0);
+    }
+}
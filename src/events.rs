@@ -0,0 +1,228 @@
+use crate::configuration::{BatteryLevel, KeyAction};
+use crate::input_recognizer::Side;
+use crossbeam_channel::{bounded, Receiver, Sender, TrySendError};
+use joycon_rs::joycon::Buttons;
+use serde::{Serialize, Serializer};
+use std::fmt::{Display, Formatter};
+use std::sync::{Arc, Mutex};
+
+/// What happens in joyshop, sent to every subscriber of the `EventBus`.
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// A binding was pressed. Bindings to `None` aren't.
+    /// Actions triggered through the control socket have no controller.
+    ActionFired {
+        controller: Option<ControllerInfo>,
+        source: Source,
+        action: KeyAction,
+    },
+    ActionReleased {
+        controller: Option<ControllerInfo>,
+        source: Source,
+        action: KeyAction,
+    },
+    DeviceConnected {
+        controller: ControllerInfo,
+        serial_number: String,
+    },
+    DeviceDisconnected {
+        controller: ControllerInfo,
+        serial_number: String,
+    },
+    /// The level or charging state of a device changed.
+    BatteryChanged {
+        serial_number: String,
+        level: BatteryLevel,
+        is_charging: bool,
+    },
+    /// The battery of a device has just dropped to `Low` or `Critical`.
+    BatteryLow {
+        serial_number: String,
+        level: BatteryLevel,
+    },
+    /// `ShowBatteryStatus` was pressed, with the status of every device.
+    BatteryReport {
+        controller: ControllerInfo,
+        text: String,
+    },
+    ProfileSwitched {
+        controller: ControllerInfo,
+        profile: String,
+    },
+    /// The config file was read again.
+    ConfigReloaded {
+        path: String,
+    },
+    Error {
+        message: String,
+    },
+}
+
+/// The controller an event comes from.
+#[derive(Clone, Debug, Serialize)]
+pub struct ControllerInfo {
+    pub slot: usize,
+    /// Name from the controller settings.
+    pub name: Option<String>,
+}
+
+impl ControllerInfo {
+    pub fn label(&self) -> String {
+        match &self.name {
+            Some(name) => name.clone(),
+            None => format!("Controller {}", self.slot + 1),
+        }
+    }
+}
+
+/// The button or stick slot of a binding.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Source {
+    Button(Side, Buttons),
+    StickSlot(Side, usize),
+    /// The control socket.
+    Remote,
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Source::Button(side, button) => write!(f, "{:?} {:?}", side, button),
+            Source::StickSlot(side, slot) => write!(f, "{:?} stick slot {}", side, slot),
+            Source::Remote => write!(f, "remote"),
+        }
+    }
+}
+
+impl Serialize for Source {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Events a subscriber can fall behind by. Later ones are dropped for it until it catches up.
+const QUEUE_LENGTH: usize = 256;
+
+/// Sends events to every subscriber. Subscribers that were dropped are forgotten.
+#[derive(Clone, Default)]
+pub struct EventBus {
+    subscribers: Arc<Mutex<Vec<Sender<Event>>>>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        EventBus::default()
+    }
+
+    /// Receives every event published from now on, as long as it keeps up.
+    pub fn subscribe(&self) -> Receiver<Event> {
+        let (tx, rx) = bounded(QUEUE_LENGTH);
+        self.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+
+    pub fn publish(&self, event: Event) {
+        self.subscribers.lock().unwrap().retain(|subscriber| {
+            match subscriber.try_send(event.clone()) {
+                Ok(()) | Err(TrySendError::Full(_)) => true,
+                Err(TrySendError::Disconnected(_)) => false,
+            }
+        });
+    }
+
+    pub fn error(&self, message: String) {
+        self.publish(Event::Error { message });
+    }
+}
+
+/// Prints the events worth a line in the console.
+pub fn log_events(events: Receiver<Event>) {
+    for event in events.iter() {
+        match event {
+            Event::ActionFired { action, .. } => match action {
+                KeyAction::KeyHold(k) => println!("[Hold] {}", k.name),
+                KeyAction::KeyClick(k) => println!("[Click] {}", k.name),
+                KeyAction::Osc(message) => println!("[OSC] {}", message.address),
+                KeyAction::OscStream(stream) => {
+                    println!("[OSC] {} {:?}", stream.address, stream.input)
+                }
+                KeyAction::MidiNote(note) => println!("[MIDI] note {}", note.note),
+                KeyAction::MidiCc(cc) => println!("[MIDI] CC {} {}", cc.controller, cc.value),
+                KeyAction::MidiProgram(program) => println!("[MIDI] program {}", program.program),
+                KeyAction::MidiCcStream(stream) => {
+                    println!("[MIDI] CC {:?} {:?}", stream.controllers, stream.input)
+                }
+                _ => {}
+            },
+            Event::ActionReleased {
+                action: KeyAction::KeyHold(k),
+                ..
+            } => println!("[Release] {}", k.name),
+            Event::ProfileSwitched { profile, .. } => println!("[Profile] {}", profile),
+            Event::ConfigReloaded { path } => println!("[Config] reloaded {}", path),
+            Event::BatteryLow { level, .. } => println!("[Battery] {:?}", level),
+            Event::Error { message } => println!("{}", message),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reloaded(path: &str) -> Event {
+        Event::ConfigReloaded { path: path.into() }
+    }
+
+    fn path(event: Event) -> String {
+        match event {
+            Event::ConfigReloaded { path } => path,
+            event => panic!("{:?} isn't a reload", event),
+        }
+    }
+
+    #[test]
+    fn every_subscriber_gets_the_events_after_it_subscribed() {
+        let bus = EventBus::new();
+        let first = bus.subscribe();
+        bus.publish(reloaded("a"));
+        let second = bus.subscribe();
+        bus.publish(reloaded("b"));
+
+        assert_eq!(first.try_iter().map(path).collect::<Vec<_>>(), ["a", "b"]);
+        assert_eq!(second.try_iter().map(path).collect::<Vec<_>>(), ["b"]);
+    }
+
+    #[test]
+    fn dropped_subscribers_are_forgotten() {
+        let bus = EventBus::new();
+        let kept = bus.subscribe();
+        drop(bus.subscribe());
+        bus.publish(reloaded("a"));
+
+        assert_eq!(bus.subscribers.lock().unwrap().len(), 1);
+        assert_eq!(kept.try_iter().count(), 1);
+    }
+
+    #[test]
+    fn slow_subscribers_miss_events_without_holding_up_others() {
+        let bus = EventBus::new();
+        let slow = bus.subscribe();
+        let fast = bus.subscribe();
+        let mut received = 0;
+        for i in 0..QUEUE_LENGTH + 10 {
+            bus.publish(reloaded(&i.to_string()));
+            received += fast.try_iter().count();
+        }
+
+        assert_eq!(received, QUEUE_LENGTH + 10);
+        assert_eq!(slow.len(), QUEUE_LENGTH);
+        assert_eq!(slow.recv().map(path).unwrap(), "0");
+        assert_eq!(bus.subscribers.lock().unwrap().len(), 2);
+
+        bus.publish(reloaded("later"));
+        assert_eq!(slow.try_iter().last().map(path).unwrap(), "later");
+    }
+}
@@ -0,0 +1,220 @@
+use crate::config_format::ConfigFormat;
+use crate::migration::migrate;
+use crate::presets::{preset_names, preset_value};
+use serde_json::{Map, Value};
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+
+/// How many files deep `extends` can go, which only a mistake would reach.
+const MAX_DEPTH: usize = 8;
+/// Settings that are merged setting by setting rather than replaced.
+const MERGED_TABLES: [&str; 2] = ["haptic_presets", "pro_controller"];
+
+/// Replaces `extends` with the settings it names, with the settings of `value` over them.
+/// `path` is the file `value` was read from, which the file `extends` names is relative to.
+pub fn resolve_extends(value: &mut Value, path: &Path) -> Result<(), String> {
+    let mut chain = path.canonicalize().into_iter().collect();
+    resolve(value, path, &mut chain)
+}
+
+fn resolve(value: &mut Value, path: &Path, chain: &mut Vec<PathBuf>) -> Result<(), String> {
+    let config = match value.as_object_mut() {
+        Some(config) => config,
+        None => return Ok(()),
+    };
+    let name = match config.remove("extends") {
+        Some(Value::String(name)) => name,
+        Some(_) => return Err("`extends` must be the name of a file or a preset".into()),
+        None => return Ok(()),
+    };
+
+    let mut base = load_base(&name, path, chain)?;
+    let config = std::mem::take(config);
+    match base.as_object_mut() {
+        Some(base) => merge(base, config),
+        None => return Err(format!("{} must be an object", name)),
+    }
+    *value = base;
+    Ok(())
+}
+
+fn load_base(name: &str, path: &Path, chain: &mut Vec<PathBuf>) -> Result<Value, String> {
+    // Names without the extension of a config file are presets.
+    if ConfigFormat::from_path(Path::new(name)).is_err() {
+        return match preset_value(name) {
+            Some(value) => Ok(value),
+            None => Err(format!(
+                "unknown preset `{}`, the presets are {}",
+                name,
+                preset_names().join(", ")
+            )),
+        };
+    }
+
+    let base_path = path.parent().unwrap_or_else(|| Path::new("")).join(name);
+    let canonical = base_path
+        .canonicalize()
+        .map_err(|e| format!("couldn't load {} error: {}", base_path.display(), e))?;
+    if chain.contains(&canonical) {
+        return Err(format!("{} extends itself", base_path.display()));
+    }
+    if chain.len() > MAX_DEPTH {
+        return Err(format!("more than {} files extend each other", MAX_DEPTH));
+    }
+    chain.push(canonical);
+
+    let format = ConfigFormat::from_path(&base_path)?;
+    let text = read_to_string(&base_path)
+        .map_err(|e| format!("couldn't load {} error: {}", base_path.display(), e))?;
+    let mut base = format.parse(&text).map_err(|(line, message)| match line {
+        Some(line) => format!(
+            "invalid {} in {} (line {}): {}",
+            format, name, line, message
+        ),
+        None => format!("invalid {} in {}: {}", format, name, message),
+    })?;
+    migrate(&mut base).map_err(|e| format!("{}: {}", name, e))?;
+    resolve(&mut base, &base_path, chain)?;
+    Ok(base)
+}
+
+/// Settings of `config` replace the ones of `base`, except tables of settings,
+/// which are merged, and profiles, which are merged with the base profile of the same name.
+fn merge(base: &mut Map<String, Value>, config: Map<String, Value>) {
+    for (key, value) in config {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Object(base_table)), Value::Object(table))
+                if MERGED_TABLES.contains(&key.as_str()) =>
+            {
+                base_table.extend(table);
+            }
+            (Some(Value::Array(base_profiles)), Value::Array(profiles)) if key == "profiles" => {
+                merge_profiles(base_profiles, profiles);
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+fn merge_profiles(base: &mut Vec<Value>, profiles: Vec<Value>) {
+    for profile in profiles {
+        let name = profile.get("name").cloned();
+        let base_profile = base
+            .iter_mut()
+            .filter_map(|base_profile| base_profile.as_object_mut())
+            .find(|base_profile| name.is_some() && base_profile.get("name") == name.as_ref());
+        match (base_profile, profile) {
+            (Some(base_profile), Value::Object(profile)) => merge(base_profile, profile),
+            (_, profile) => base.push(profile),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::fs::{create_dir_all, remove_dir_all, write};
+
+    /// A directory of config files for one test.
+    fn files(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("joyshop-{}-{}", name, std::process::id()));
+        create_dir_all(&dir).unwrap();
+        for (file, text) in files {
+            write(dir.join(file), text).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn settings_replace_the_base_and_tables_are_merged() {
+        let mut base = json!({
+            "show_tooltip": true,
+            "zl": "None",
+            "haptic_presets": { "a": [], "b": [] },
+            "pro_controller": { "zl": "None", "zr": "None" },
+            "profiles": [{ "name": "Paint", "zl": "None", "zr": "None" }],
+        });
+        let config = json!({
+            "show_tooltip": false,
+            "haptic_presets": { "b": [{}], "c": [] },
+            "pro_controller": { "zr": "NextProfile" },
+            "profiles": [
+                { "name": "Paint", "zr": "NextProfile" },
+                { "name": "Sculpt" },
+            ],
+        });
+        merge(
+            base.as_object_mut().unwrap(),
+            config.as_object().unwrap().clone(),
+        );
+        assert_eq!(
+            base,
+            json!({
+                "show_tooltip": false,
+                "zl": "None",
+                "haptic_presets": { "a": [], "b": [{}], "c": [] },
+                "pro_controller": { "zl": "None", "zr": "NextProfile" },
+                "profiles": [
+                    { "name": "Paint", "zl": "None", "zr": "NextProfile" },
+                    { "name": "Sculpt" },
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn files_are_relative_to_the_one_that_extends_them() {
+        let dir = files(
+            "extends",
+            &[
+                ("shared.toml", "show_tooltip = false\nzl = \"None\"\n"),
+                (
+                    "middle.json",
+                    r#"{ "extends": "shared.toml", "zl": "NextProfile" }"#,
+                ),
+            ],
+        );
+        let mut value = json!({ "extends": "middle.json", "start_profile": "Paint" });
+        let resolved = resolve_extends(&mut value, &dir.join("settings.json"));
+        remove_dir_all(&dir).unwrap();
+
+        assert_eq!(resolved, Ok(()));
+        assert_eq!(value["show_tooltip"], json!(false));
+        assert_eq!(value["zl"], json!("NextProfile"));
+        assert_eq!(value["start_profile"], json!("Paint"));
+        assert_eq!(value["version"], json!(1));
+        assert!(value.get("extends").is_none());
+    }
+
+    #[test]
+    fn files_that_extend_each_other_are_refused() {
+        let dir = files(
+            "cycle",
+            &[
+                ("a.json", r#"{ "extends": "b.json" }"#),
+                ("b.json", r#"{ "extends": "a.json" }"#),
+            ],
+        );
+        let path = dir.join("a.json");
+        let mut value = json!({ "extends": "b.json" });
+        let resolved = resolve_extends(&mut value, &path);
+        remove_dir_all(&dir).unwrap();
+
+        assert_eq!(resolved, Err(format!("{} extends itself", path.display())));
+    }
+
+    #[test]
+    fn names_without_an_extension_are_presets() {
+        let mut value = json!({ "extends": "krita", "show_tooltip": false });
+        resolve_extends(&mut value, Path::new("settings.json")).unwrap();
+        assert_eq!(value["zl"], preset_value("krita").unwrap()["zl"]);
+        assert_eq!(value["show_tooltip"], json!(false));
+
+        let mut unknown = json!({ "extends": "paint" });
+        let error = resolve_extends(&mut unknown, Path::new("settings.json")).unwrap_err();
+        assert!(error.starts_with("unknown preset `paint`"), "{}", error);
+    }
+}
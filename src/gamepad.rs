@@ -0,0 +1,600 @@
+use crate::configuration::Config;
+use crate::controller::ControllerMessage;
+use crate::events::EventBus;
+use crate::input_recognizer::{Side, SideInput};
+use crate::joyshop::{join_controller, leave_controller};
+use crossbeam_channel::{unbounded, Sender};
+use joycon_rs::joycon::input_report_mode::AnalogStickData;
+use joycon_rs::joycon::Buttons;
+use once_cell::sync::Lazy;
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::CStr;
+use std::fs::{read_dir, File};
+use std::io::{ErrorKind, Read};
+use std::mem::{size_of, zeroed};
+use std::os::raw::c_char;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::sleep;
+use std::time::Duration;
+
+const EV_SYN: u16 = 0x00;
+const EV_KEY: u16 = 0x01;
+const EV_ABS: u16 = 0x03;
+const SYN_REPORT: u16 = 0x00;
+const SYN_DROPPED: u16 = 0x03;
+
+const ABS_X: u16 = 0x00;
+const ABS_Y: u16 = 0x01;
+const ABS_Z: u16 = 0x02;
+const ABS_RX: u16 = 0x03;
+const ABS_RY: u16 = 0x04;
+const ABS_RZ: u16 = 0x05;
+const ABS_HAT0X: u16 = 0x10;
+const ABS_HAT0Y: u16 = 0x11;
+const AXES: [u16; 8] = [
+    ABS_X, ABS_Y, ABS_Z, ABS_RX, ABS_RY, ABS_RZ, ABS_HAT0X, ABS_HAT0Y,
+];
+
+const BTN_GAMEPAD: u16 = 0x130;
+const KEY_MAX: usize = 0x2FF;
+
+/// JoyCons, the Pro Controller and the charging grip, which hid-nintendo also exposes as event
+/// devices. joyshop reads them as JoyCons, so they aren't read twice.
+const NINTENDO_VENDOR: u16 = 0x057E;
+const NINTENDO_PRODUCTS: [u16; 5] = [0x2006, 0x2007, 0x2008, 0x2009, 0x200E];
+
+/// Buttons by their position, so the south button is B like on a Switch controller.
+const BUTTONS: [(u16, Buttons); 18] = [
+    (0x130, Buttons::B),
+    (0x131, Buttons::A),
+    (0x133, Buttons::X),
+    (0x134, Buttons::Y),
+    (0x136, Buttons::L),
+    (0x137, Buttons::R),
+    (0x138, Buttons::ZL),
+    (0x139, Buttons::ZR),
+    (0x13A, Buttons::Minus),
+    (0x13B, Buttons::Plus),
+    (0x13C, Buttons::Home),
+    (0x13D, Buttons::LStick),
+    (0x13E, Buttons::RStick),
+    (0x220, Buttons::Up),
+    (0x221, Buttons::Down),
+    (0x222, Buttons::Left),
+    (0x223, Buttons::Right),
+    // KEY_RECORD, the share button of Xbox pads.
+    (0xA7, Buttons::Capture),
+];
+
+const SCAN_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Event devices that are being read.
+static OPENED: Lazy<Mutex<BTreeSet<PathBuf>>> = Lazy::new(|| Mutex::new(BTreeSet::new()));
+
+/// Reads generic gamepads through evdev as controllers with both sides.
+/// Devices are picked up as they appear, like JoyCons.
+pub fn run_gamepads(config: Arc<RwLock<Box<Config>>>, events: EventBus) {
+    let mut device_id = 0;
+
+    loop {
+        let configured = match config.read() {
+            Ok(config) => config.gamepads.clone(),
+            Err(_) => return,
+        };
+
+        for path in gamepad_paths(&configured) {
+            if OPENED.lock().unwrap().contains(&path) {
+                continue;
+            }
+            let file = match File::open(&path) {
+                Ok(file) => file,
+                Err(_) => continue,
+            };
+            // Configured paths are trusted, so a recorded event file can stand in for a device.
+            if configured.is_empty() && (!is_gamepad(&file) || is_joycon(&file)) {
+                continue;
+            }
+
+            println!("{} Connected ({})", device_name(&file), path.display());
+            OPENED.lock().unwrap().insert(path.clone());
+            device_id += 1;
+
+            let sides: &'static [Side] = &[Side::Left, Side::Right];
+            let (controller_id, controller) = join_controller(sides, &config, &events);
+            let events = events.clone();
+            std::thread::spawn(move || {
+                handle_gamepad_input(file, &path, device_id, controller, events);
+                leave_controller(controller_id, sides);
+                OPENED.lock().unwrap().remove(&path);
+            });
+        }
+
+        sleep(SCAN_INTERVAL);
+    }
+}
+
+/// Gamepads under /dev/input with their names.
+pub fn list_gamepads() -> Vec<(PathBuf, String)> {
+    gamepad_paths(&[])
+        .into_iter()
+        .filter_map(|path| {
+            let file = File::open(&path).ok()?;
+            if !is_gamepad(&file) || is_joycon(&file) {
+                return None;
+            }
+            let name = device_name(&file);
+            Some((path, name))
+        })
+        .collect()
+}
+
+fn gamepad_paths(configured: &[String]) -> Vec<PathBuf> {
+    if !configured.is_empty() {
+        return configured.iter().map(PathBuf::from).collect();
+    }
+
+    let mut paths = match read_dir("/dev/input") {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| name.starts_with("event"))
+            })
+            .collect::<Vec<_>>(),
+        Err(_) => Vec::new(),
+    };
+    paths.sort();
+    paths
+}
+
+fn handle_gamepad_input(
+    mut file: File,
+    path: &Path,
+    device_id: usize,
+    controller: Sender<ControllerMessage>,
+    events: EventBus,
+) {
+    let serial_number = device_uniq(&file).unwrap_or_else(|| path.display().to_string());
+    let (commands_tx, commands) = unbounded();
+    let connected = ControllerMessage::Connected {
+        device_id,
+        serial_number,
+        sides: &[Side::Left, Side::Right],
+        commands: commands_tx,
+    };
+    if controller.send(connected).is_err() {
+        return;
+    }
+
+    let mut gamepad = Gamepad::new(&file);
+    loop {
+        let inputs = match gamepad.read_report(&mut file) {
+            Ok(inputs) => inputs,
+            Err(e) => {
+                if e.kind() != ErrorKind::UnexpectedEof {
+                    events.error(format!("Gamepad error occurred: {:?}", e));
+                }
+                break;
+            }
+        };
+
+        let input = ControllerMessage::Input { device_id, inputs };
+        if controller.send(input).is_err() {
+            break;
+        }
+
+        // Gamepads have no player lights, and their rumble isn't driven yet.
+        for _ in commands.try_iter() {}
+    }
+
+    println!("{} Disconnected", path.display());
+    let _ = controller.send(ControllerMessage::Disconnected { device_id });
+}
+
+enum Update {
+    Pending,
+    Report,
+    /// A report after events were dropped, so the state has to be read from the device.
+    Resync,
+}
+
+/// State of a gamepad, built up from its events until a report completes.
+struct Gamepad {
+    buttons: Vec<Buttons>,
+    axes: BTreeMap<u16, i32>,
+    ranges: BTreeMap<u16, (i32, i32)>,
+    dropped: bool,
+}
+
+impl Gamepad {
+    fn new(file: &File) -> Self {
+        let mut gamepad = Gamepad {
+            buttons: Vec::new(),
+            axes: BTreeMap::new(),
+            ranges: AXES
+                .iter()
+                .map(|&axis| (axis, axis_range(file, axis)))
+                .collect(),
+            dropped: false,
+        };
+        gamepad.read_state(file);
+        gamepad
+    }
+
+    /// Reads events until a report completes, and returns the input of the gamepad then.
+    fn read_report(&mut self, file: &mut File) -> std::io::Result<Vec<(Side, SideInput)>> {
+        let mut buf = [0u8; size_of::<libc::input_event>()];
+        loop {
+            file.read_exact(&mut buf)?;
+            let event = unsafe { (buf.as_ptr() as *const libc::input_event).read_unaligned() };
+            match self.update(event.type_, event.code, event.value) {
+                Update::Pending => {}
+                Update::Report => return Ok(self.inputs()),
+                Update::Resync => {
+                    self.read_state(file);
+                    return Ok(self.inputs());
+                }
+            }
+        }
+    }
+
+    fn update(&mut self, type_: u16, code: u16, value: i32) -> Update {
+        match (type_, code) {
+            (EV_SYN, SYN_REPORT) if self.dropped => {
+                self.dropped = false;
+                return Update::Resync;
+            }
+            (EV_SYN, SYN_REPORT) => return Update::Report,
+            // Events were lost, so what follows until the next report can't be trusted.
+            (EV_SYN, SYN_DROPPED) => self.dropped = true,
+            _ if self.dropped => {}
+            (EV_KEY, _) => {
+                let button = BUTTONS.iter().find(|(c, _)| *c == code).map(|(_, b)| *b);
+                if let Some(button) = button {
+                    self.buttons.retain(|b| *b != button);
+                    if value != 0 {
+                        self.buttons.push(button);
+                    }
+                }
+            }
+            (EV_ABS, _) => {
+                if let Some(axis) = self.axes.get_mut(&code) {
+                    *axis = value;
+                }
+            }
+            _ => {}
+        }
+        Update::Pending
+    }
+
+    /// Takes the buttons and axes from the device, as they are now.
+    /// What the device can't tell is released, so no button stays held.
+    fn read_state(&mut self, file: &File) {
+        let keys = key_state(file);
+        self.set_state(keys.as_ref().map(|keys| &keys[..]), |axis| {
+            abs_info(file, axis).map(|info| info.value)
+        });
+    }
+
+    fn set_state(&mut self, keys: Option<&[u8]>, axis_value: impl Fn(u16) -> Option<i32>) {
+        let is_set = |keys: &[u8], code: u16| {
+            let code = code as usize;
+            keys.get(code / 8)
+                .is_some_and(|bits| bits & (1 << (code % 8)) != 0)
+        };
+        self.buttons = BUTTONS
+            .iter()
+            .filter(|(code, _)| keys.is_some_and(|keys| is_set(keys, *code)))
+            .map(|(_, button)| *button)
+            .collect();
+
+        for (&axis, &(min, max)) in self.ranges.iter() {
+            let rest = if is_trigger(axis) {
+                min
+            } else {
+                (min + max) / 2
+            };
+            self.axes.insert(axis, axis_value(axis).unwrap_or(rest));
+        }
+    }
+
+    fn inputs(&self) -> Vec<(Side, SideInput)> {
+        let mut buttons = self.buttons.clone();
+        let mut press = |button: Buttons, is_pressed: bool| {
+            if is_pressed && !buttons.contains(&button) {
+                buttons.push(button);
+            }
+        };
+        press(Buttons::Left, self.axis(ABS_HAT0X) < -0.5);
+        press(Buttons::Right, self.axis(ABS_HAT0X) > 0.5);
+        press(Buttons::Up, self.axis(ABS_HAT0Y) < -0.5);
+        press(Buttons::Down, self.axis(ABS_HAT0Y) > 0.5);
+        // Analog triggers count as pressed from halfway.
+        press(Buttons::ZL, self.axis(ABS_Z) > 0.5);
+        press(Buttons::ZR, self.axis(ABS_RZ) > 0.5);
+
+        vec![
+            (
+                Side::Left,
+                SideInput {
+                    buttons: buttons.clone(),
+                    stick: self.stick(ABS_X, ABS_Y),
+                    battery: None,
+                    motion: None,
+                },
+            ),
+            (
+                Side::Right,
+                SideInput {
+                    buttons,
+                    stick: self.stick(ABS_RX, ABS_RY),
+                    battery: None,
+                    motion: None,
+                },
+            ),
+        ]
+    }
+
+    /// Value of an axis from -1 to 1, or 0 to 1 for triggers.
+    fn axis(&self, axis: u16) -> f64 {
+        let (min, max) = self.ranges[&axis];
+        let value = self.axes[&axis];
+        if max <= min {
+            return 0.0;
+        }
+
+        let position = (value - min) as f64 / (max - min) as f64;
+        if is_trigger(axis) {
+            position
+        } else {
+            position * 2.0 - 1.0
+        }
+    }
+
+    /// Converts to the JoyCon's 12 bit stick data, where up is positive.
+    fn stick(&self, horizontal: u16, vertical: u16) -> AnalogStickData {
+        let to_stick = |value: f64| ((value + 1.0) / 2.0 * 4095.0).round().clamp(0.0, 4095.0);
+        AnalogStickData {
+            horizontal: to_stick(self.axis(horizontal)) as u16,
+            vertical: to_stick(-self.axis(vertical)) as u16,
+        }
+    }
+}
+
+fn is_trigger(axis: u16) -> bool {
+    axis == ABS_Z || axis == ABS_RZ
+}
+
+/// Range of an axis, with the usual one for devices that can't tell.
+fn axis_range(file: &File, axis: u16) -> (i32, i32) {
+    if let Some(info) = abs_info(file, axis).filter(|info| info.maximum > info.minimum) {
+        return (info.minimum, info.maximum);
+    }
+
+    match axis {
+        ABS_Z | ABS_RZ => (0, 255),
+        ABS_HAT0X | ABS_HAT0Y => (-1, 1),
+        _ => (-32768, 32767),
+    }
+}
+
+/// `EVIOCGABS`, the range and current value of an axis.
+fn abs_info(file: &File, axis: u16) -> Option<libc::input_absinfo> {
+    let mut info = unsafe { zeroed::<libc::input_absinfo>() };
+    let request = ior(0x40 + axis as u64, size_of::<libc::input_absinfo>());
+    if unsafe { libc::ioctl(file.as_raw_fd(), request, &mut info) } < 0 {
+        return None;
+    }
+    Some(info)
+}
+
+/// `EVIOCGKEY`, a bit for each key that is down.
+fn key_state(file: &File) -> Option<[u8; KEY_MAX / 8 + 1]> {
+    let mut bits = [0u8; KEY_MAX / 8 + 1];
+    let request = ior(0x18, bits.len());
+    if unsafe { libc::ioctl(file.as_raw_fd(), request, bits.as_mut_ptr()) } < 0 {
+        return None;
+    }
+    Some(bits)
+}
+
+/// Whether hid-nintendo made the device for a JoyCon or a Pro Controller.
+fn is_joycon(file: &File) -> bool {
+    let mut id = unsafe { zeroed::<libc::input_id>() };
+    let request = ior(0x02, size_of::<libc::input_id>());
+    if unsafe { libc::ioctl(file.as_raw_fd(), request, &mut id) } < 0 {
+        return false;
+    }
+    id.vendor == NINTENDO_VENDOR && NINTENDO_PRODUCTS.contains(&id.product)
+}
+
+fn is_gamepad(file: &File) -> bool {
+    let mut bits = [0u8; KEY_MAX / 8 + 1];
+    let request = ior(0x20 + EV_KEY as u64, bits.len());
+    if unsafe { libc::ioctl(file.as_raw_fd(), request, bits.as_mut_ptr()) } < 0 {
+        return false;
+    }
+
+    let code = BTN_GAMEPAD as usize;
+    bits[code / 8] & (1 << (code % 8)) != 0
+}
+
+fn device_name(file: &File) -> String {
+    read_string(file, 0x06).unwrap_or_else(|| "Gamepad".to_string())
+}
+
+/// The unique id of the device, the MAC address for bluetooth gamepads.
+fn device_uniq(file: &File) -> Option<String> {
+    read_string(file, 0x08)
+}
+
+fn read_string(file: &File, number: u64) -> Option<String> {
+    let mut buf = [0 as c_char; 256];
+    let request = ior(number, buf.len());
+    if unsafe { libc::ioctl(file.as_raw_fd(), request, buf.as_mut_ptr()) } < 0 {
+        return None;
+    }
+
+    let text = unsafe { CStr::from_ptr(buf.as_ptr()) }.to_string_lossy();
+    if text.is_empty() {
+        return None;
+    }
+    Some(text.into_owned())
+}
+
+/// `_IOR('E', number, size)` of the evdev ioctls.
+fn ior(number: u64, size: usize) -> libc::c_ulong {
+    ((2 << 30) | ((size as u64) << 16) | (0x45 << 8) | number) as libc::c_ulong
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    /// Writes the events to a file, the way they come from an event device.
+    fn recording(name: &str, events: &[(u16, u16, i32)]) -> File {
+        let path = std::env::temp_dir().join(format!("joyshop-{}-{}", name, std::process::id()));
+        let mut file = File::create(&path).unwrap();
+        for &(type_, code, value) in events {
+            let mut event = unsafe { zeroed::<libc::input_event>() };
+            event.type_ = type_;
+            event.code = code;
+            event.value = value;
+            let bytes = unsafe {
+                std::slice::from_raw_parts(
+                    &event as *const libc::input_event as *const u8,
+                    size_of::<libc::input_event>(),
+                )
+            };
+            file.write_all(bytes).unwrap();
+        }
+        let file = File::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        file
+    }
+
+    fn reports(mut file: File) -> Vec<Vec<(Side, SideInput)>> {
+        let mut gamepad = Gamepad::new(&file);
+        let mut reports = Vec::new();
+        loop {
+            match gamepad.read_report(&mut file) {
+                Ok(inputs) => reports.push(inputs),
+                Err(e) => {
+                    assert_eq!(e.kind(), ErrorKind::UnexpectedEof);
+                    return reports;
+                }
+            }
+        }
+    }
+
+    fn buttons(inputs: &[(Side, SideInput)]) -> &[Buttons] {
+        &inputs[0].1.buttons
+    }
+
+    #[test]
+    fn buttons_are_held_until_released() {
+        let file = recording(
+            "buttons",
+            &[
+                (EV_KEY, 0x131, 1),
+                (EV_SYN, SYN_REPORT, 0),
+                (EV_KEY, 0x130, 1),
+                (EV_ABS, ABS_HAT0X, -1),
+                (EV_ABS, ABS_RZ, 255),
+                (EV_SYN, SYN_REPORT, 0),
+                (EV_KEY, 0x131, 0),
+                (EV_ABS, ABS_HAT0X, 0),
+                (EV_SYN, SYN_REPORT, 0),
+            ],
+        );
+
+        let reports = reports(file);
+        assert_eq!(reports.len(), 3);
+        assert_eq!(buttons(&reports[0]), [Buttons::A]);
+        assert_eq!(
+            buttons(&reports[1]),
+            [Buttons::A, Buttons::B, Buttons::Left, Buttons::ZR]
+        );
+        assert_eq!(buttons(&reports[2]), [Buttons::B, Buttons::ZR]);
+        for report in &reports {
+            assert_eq!(report[0].1.buttons, report[1].1.buttons);
+        }
+    }
+
+    #[test]
+    fn sticks_go_to_their_side() {
+        let file = recording(
+            "sticks",
+            &[
+                (EV_ABS, ABS_X, 32767),
+                (EV_ABS, ABS_Y, -32768),
+                (EV_SYN, SYN_REPORT, 0),
+                (EV_ABS, ABS_X, 0),
+                (EV_ABS, ABS_RY, 32767),
+                (EV_SYN, SYN_REPORT, 0),
+            ],
+        );
+
+        let reports = reports(file);
+        let stick = |report: usize, side: usize| {
+            let stick = &reports[report][side].1.stick;
+            (stick.horizontal, stick.vertical)
+        };
+        // Up is positive, unlike on event devices.
+        assert_eq!(stick(0, 0), (4095, 4095));
+        assert_eq!(stick(0, 1), (2048, 2047));
+        assert_eq!(stick(1, 0), (2048, 4095));
+        assert_eq!(stick(1, 1), (2048, 0));
+    }
+
+    #[test]
+    fn dropped_events_are_not_applied() {
+        let file = recording(
+            "dropped",
+            &[
+                (EV_KEY, 0x130, 1),
+                (EV_ABS, ABS_X, 32767),
+                (EV_SYN, SYN_REPORT, 0),
+                (EV_SYN, SYN_DROPPED, 0),
+                (EV_KEY, 0x131, 1),
+                (EV_SYN, SYN_REPORT, 0),
+                (EV_KEY, 0x133, 1),
+                (EV_SYN, SYN_REPORT, 0),
+            ],
+        );
+
+        let reports = reports(file);
+        assert_eq!(reports.len(), 3);
+        assert_eq!(buttons(&reports[0]), [Buttons::B]);
+        // A recording can't be asked for its state, so everything is released.
+        assert_eq!(buttons(&reports[1]), []);
+        assert_eq!(reports[1][0].1.stick.horizontal, 2048);
+        assert_eq!(buttons(&reports[2]), [Buttons::X]);
+    }
+
+    #[test]
+    fn state_after_a_drop_is_the_one_of_the_device() {
+        let file = recording("state", &[]);
+        let mut gamepad = Gamepad::new(&file);
+        gamepad.update(EV_KEY, 0x130, 1);
+
+        let mut keys = [0u8; KEY_MAX / 8 + 1];
+        keys[0x131 / 8] |= 1 << (0x131 % 8);
+        keys[0x220 / 8] |= 1 << (0x220 % 8);
+        gamepad.set_state(Some(&keys), |axis| match axis {
+            ABS_Y => Some(32767),
+            ABS_Z => Some(255),
+            _ => None,
+        });
+
+        let inputs = gamepad.inputs();
+        assert_eq!(buttons(&inputs), [Buttons::A, Buttons::Up, Buttons::ZL]);
+        assert_eq!(inputs[0].1.stick.horizontal, 2048);
+        assert_eq!(inputs[0].1.stick.vertical, 0);
+    }
+}
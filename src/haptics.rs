@@ -0,0 +1,321 @@
+use crate::configuration::{
+    default_haptic_presets, Config, Haptic, HapticSegment, KeyAction, Motor,
+};
+use crate::input_recognizer::Side;
+use joycon_rs::prelude::*;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Amplitudes are clamped to 1.0, below the 1.003 that joycon-rs (`Rumble::is_safe`) gives as
+/// the limit for the linear resonant actuators of JoyCons.
+const MAX_SAFE_AMPLITUDE: f32 = 1.0;
+
+pub fn action_haptic(action: &KeyAction) -> Option<&Haptic> {
+    match action {
+        KeyAction::KeyHold(k) | KeyAction::KeyClick(k) => k.haptic.as_ref(),
+        _ => None,
+    }
+}
+
+pub fn resolve_haptic(config: &Config, haptic: &Haptic) -> Option<Vec<HapticSegment>> {
+    match haptic {
+        Haptic::Preset(name) => resolve_preset(config, name),
+        Haptic::Pattern(segments) => Some(segments.clone()),
+    }
+}
+
+pub fn resolve_preset(config: &Config, name: &str) -> Option<Vec<HapticSegment>> {
+    match config.haptic_presets.get(name) {
+        Some(segments) => Some(segments.clone()),
+        None => default_haptic_presets().remove(name),
+    }
+}
+
+/// Adapts a pattern triggered by the input of `side` to one device of the controller.
+///
+/// A Pro Controller has both motors and plays the pattern as it is. A JoyCon has one:
+/// `Left` and `Right` pick the JoyCon of that side and `Both` the one that was used.
+/// Returns `None` when the pattern doesn't play on the device at all.
+pub fn device_segments(
+    segments: &[HapticSegment],
+    device_sides: &[Side],
+    side: Side,
+) -> Option<Vec<HapticSegment>> {
+    let device_side = match device_sides {
+        [device_side] => *device_side,
+        _ => return Some(segments.to_vec()),
+    };
+
+    let plays = |motor: Motor| match motor {
+        Motor::Both => device_side == side,
+        Motor::Left => device_side == Side::Left,
+        Motor::Right => device_side == Side::Right,
+    };
+    if !segments.iter().any(|s| plays(s.motor)) {
+        return None;
+    }
+
+    // Segments for the other JoyCon stay as pauses to keep the timing.
+    let segments = segments
+        .iter()
+        .map(|s| HapticSegment {
+            amplitude: if plays(s.motor) { s.amplitude } else { 0.0 },
+            motor: Motor::Both,
+            ..s.clone()
+        })
+        .collect();
+    Some(segments)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Overlap {
+    /// Replace whatever is playing on the motors the new pattern uses.
+    /// Motors it doesn't use keep playing, so patterns for different motors merge.
+    Preempt,
+    /// Start after everything already scheduled has finished.
+    Queue,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Step {
+    rumble: Rumble,
+    duration: Duration,
+}
+
+#[derive(Default)]
+struct Track {
+    steps: VecDeque<Step>,
+    step_started: Option<Instant>,
+}
+
+impl Track {
+    fn remaining(&self, now: Instant) -> Duration {
+        let total: Duration = self.steps.iter().map(|s| s.duration).sum();
+        let elapsed = match self.step_started {
+            Some(started) => now.saturating_duration_since(started),
+            None => Duration::from_millis(0),
+        };
+        total.checked_sub(elapsed).unwrap_or_default()
+    }
+
+    fn push(&mut self, step: Step, now: Instant) {
+        if self.steps.is_empty() {
+            self.step_started = Some(now);
+        }
+        self.steps.push_back(step);
+    }
+
+    fn advance(&mut self, now: Instant) -> Rumble {
+        while let (Some(step), Some(started)) = (self.steps.front(), self.step_started) {
+            if now < started + step.duration {
+                return step.rumble;
+            }
+            self.step_started = Some(started + step.duration);
+            self.steps.pop_front();
+        }
+
+        self.step_started = None;
+        Rumble::stop()
+    }
+}
+
+/// Plays rumble patterns for one device without blocking its input loop.
+///
+/// Call `tick` on every loop iteration and send the returned state to the driver.
+/// Resolution is bound to the input report rate (about 16ms in standard full mode).
+pub struct HapticScheduler {
+    tracks: [Track; 2],
+    current: (Rumble, Rumble),
+}
+
+impl HapticScheduler {
+    pub fn new() -> Self {
+        HapticScheduler {
+            tracks: [Track::default(), Track::default()],
+            current: (Rumble::stop(), Rumble::stop()),
+        }
+    }
+
+    pub fn schedule(&mut self, segments: &[HapticSegment], overlap: Overlap, now: Instant) {
+        let uses = |motor: Motor, index: usize| match motor {
+            Motor::Both => true,
+            Motor::Left => index == 0,
+            Motor::Right => index == 1,
+        };
+
+        if overlap == Overlap::Queue {
+            // Keep both motors in step so the queued pattern starts at the same time on each.
+            let start = self.tracks.iter().map(|t| t.remaining(now)).max();
+            for track in self.tracks.iter_mut() {
+                let gap = start.unwrap_or_default() - track.remaining(now);
+                if gap > Duration::from_millis(0) {
+                    track.push(silence(gap), now);
+                }
+            }
+        }
+
+        for (index, track) in self.tracks.iter_mut().enumerate() {
+            if !segments.iter().any(|s| uses(s.motor, index)) {
+                continue;
+            }
+
+            if overlap == Overlap::Preempt {
+                track.steps.clear();
+            }
+
+            for segment in segments {
+                let duration = Duration::from_millis(segment.duration_ms);
+                let step = if uses(segment.motor, index) {
+                    Step {
+                        rumble: Rumble::new(
+                            segment.frequency,
+                            segment.amplitude.min(MAX_SAFE_AMPLITUDE),
+                        ),
+                        duration,
+                    }
+                } else {
+                    silence(duration)
+                };
+                track.push(step, now);
+            }
+        }
+    }
+
+    /// Returns the rumble state to send when it differs from the one last returned.
+    pub fn tick(&mut self, now: Instant) -> Option<(Option<Rumble>, Option<Rumble>)> {
+        let left = self.tracks[0].advance(now);
+        let right = self.tracks[1].advance(now);
+        if (left, right) == self.current {
+            return None;
+        }
+
+        self.current = (left, right);
+        Some((Some(left), Some(right)))
+    }
+}
+
+fn silence(duration: Duration) -> Step {
+    Step {
+        rumble: Rumble::stop(),
+        duration,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(frequency: f32, amplitude: f32, duration_ms: u64, motor: Motor) -> HapticSegment {
+        HapticSegment {
+            frequency,
+            amplitude,
+            duration_ms,
+            motor,
+        }
+    }
+
+    fn at(start: Instant, ms: u64) -> Instant {
+        start + Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn plays_segments_in_order_then_stops() {
+        let start = Instant::now();
+        let mut scheduler = HapticScheduler::new();
+        let pattern = [
+            segment(320.0, 0.5, 50, Motor::Both),
+            segment(160.0, 0.8, 50, Motor::Both),
+        ];
+        scheduler.schedule(&pattern, Overlap::Preempt, start);
+
+        let first = Rumble::new(320.0, 0.5);
+        assert_eq!(
+            scheduler.tick(at(start, 10)),
+            Some((Some(first), Some(first)))
+        );
+        assert_eq!(scheduler.tick(at(start, 40)), None);
+        let second = Rumble::new(160.0, 0.8);
+        assert_eq!(
+            scheduler.tick(at(start, 60)),
+            Some((Some(second), Some(second)))
+        );
+        let stop = Rumble::stop();
+        assert_eq!(
+            scheduler.tick(at(start, 100)),
+            Some((Some(stop), Some(stop)))
+        );
+    }
+
+    #[test]
+    fn preempt_only_replaces_the_motors_it_uses() {
+        let start = Instant::now();
+        let mut scheduler = HapticScheduler::new();
+        scheduler.schedule(
+            &[segment(320.0, 0.5, 100, Motor::Both)],
+            Overlap::Preempt,
+            start,
+        );
+        scheduler.schedule(
+            &[segment(160.0, 0.8, 50, Motor::Right)],
+            Overlap::Preempt,
+            at(start, 10),
+        );
+
+        let left = Rumble::new(320.0, 0.5);
+        assert_eq!(
+            scheduler.tick(at(start, 20)),
+            Some((Some(left), Some(Rumble::new(160.0, 0.8))))
+        );
+        // The right motor stops after its own pattern while the left one keeps going.
+        assert_eq!(
+            scheduler.tick(at(start, 70)),
+            Some((Some(left), Some(Rumble::stop())))
+        );
+    }
+
+    #[test]
+    fn queue_starts_after_the_longest_track() {
+        let start = Instant::now();
+        let mut scheduler = HapticScheduler::new();
+        scheduler.schedule(
+            &[segment(320.0, 0.5, 100, Motor::Left)],
+            Overlap::Preempt,
+            start,
+        );
+        scheduler.schedule(
+            &[segment(160.0, 0.8, 50, Motor::Both)],
+            Overlap::Queue,
+            start,
+        );
+
+        let stop = Rumble::stop();
+        assert_eq!(
+            scheduler.tick(at(start, 50)),
+            Some((Some(Rumble::new(320.0, 0.5)), Some(stop)))
+        );
+        let queued = Rumble::new(160.0, 0.8);
+        assert_eq!(
+            scheduler.tick(at(start, 120)),
+            Some((Some(queued), Some(queued)))
+        );
+        assert_eq!(
+            scheduler.tick(at(start, 150)),
+            Some((Some(stop), Some(stop)))
+        );
+    }
+
+    #[test]
+    fn amplitudes_are_clamped() {
+        let start = Instant::now();
+        let mut scheduler = HapticScheduler::new();
+        scheduler.schedule(
+            &[segment(320.0, 1.5, 50, Motor::Both)],
+            Overlap::Preempt,
+            start,
+        );
+
+        let (left, _) = scheduler.tick(start).unwrap();
+        assert_eq!(left.unwrap().amplitude(), MAX_SAFE_AMPLITUDE);
+        assert!(left.unwrap().is_safe());
+    }
+}
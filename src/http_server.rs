@@ -0,0 +1,441 @@
+use crate::control::{answer_request, status, ControlState};
+use crate::events::Event;
+use serde_json::{json, Map, Value};
+use std::io::{ErrorKind, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread::spawn;
+use std::time::Duration;
+use tungstenite::handshake::derive_accept_key;
+use tungstenite::protocol::Role;
+use tungstenite::{Message, WebSocket};
+
+/// Largest request read, headers and body together.
+const MAX_REQUEST: usize = 64 * 1024;
+
+#[derive(Clone)]
+struct HttpState {
+    control: ControlState,
+    /// The last `action_fired` event.
+    last_action: Arc<Mutex<Option<Value>>>,
+}
+
+struct HttpRequest {
+    method: String,
+    /// Without the query.
+    path: String,
+    host: Option<String>,
+    origin: Option<String>,
+    upgrade: Option<String>,
+    connection: Option<String>,
+    websocket_key: Option<String>,
+    body: Vec<u8>,
+}
+
+/// Serves the state of joyshop and the requests of the control socket over HTTP and WebSocket
+/// on 127.0.0.1, each connection on a thread of its own.
+pub fn serve_http(port: u16, control: ControlState) {
+    let listener = match TcpListener::bind((Ipv4Addr::LOCALHOST, port)) {
+        Ok(listener) => listener,
+        Err(e) => {
+            println!("couldn't listen on 127.0.0.1:{} error: {}", port, e);
+            return;
+        }
+    };
+
+    let state = HttpState {
+        control,
+        last_action: Arc::new(Mutex::new(None)),
+    };
+    let events = state.control.events.subscribe();
+    let last_action = state.last_action.clone();
+    spawn(move || {
+        for event in events.iter() {
+            if let Event::ActionFired { .. } = event {
+                *last_action.lock().unwrap() = Some(serde_json::to_value(&event).unwrap());
+            }
+        }
+    });
+
+    println!("[HTTP] http://127.0.0.1:{}", port);
+    for stream in listener.incoming().flatten() {
+        let state = state.clone();
+        spawn(move || handle_connection(stream, &state));
+    }
+}
+
+/// Answers one request and closes the connection, unless it becomes a WebSocket.
+fn handle_connection(mut stream: TcpStream, state: &HttpState) {
+    let _ = stream.set_read_timeout(Some(Duration::from_secs(5)));
+    let request = match read_request(&mut stream) {
+        Ok(request) => request,
+        Err(e) => {
+            let _ = respond(&mut stream, 400, None, Some(&error(e)));
+            return;
+        }
+    };
+
+    if !is_local(&request) {
+        let message = "only pages on this computer can use joyshop".to_string();
+        let _ = respond(&mut stream, 403, None, Some(&error(message)));
+        return;
+    }
+
+    let origin = request.origin.as_deref();
+    let response = match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/status") => {
+            let _ = respond(&mut stream, 200, origin, Some(&status_value(state)));
+            return;
+        }
+        ("GET", "/events") => match &request.websocket_key {
+            Some(key) if is_websocket_upgrade(&request) => {
+                return serve_websocket(stream, key, state)
+            }
+            _ => error("/events is a WebSocket".into()),
+        },
+        ("POST", "/trigger") => match serde_json::from_slice::<Value>(&request.body) {
+            Ok(Value::Object(mut request)) => {
+                request.insert("command".into(), "trigger".into());
+                answer_request(Value::Object(request), &state.control)
+            }
+            Ok(_) => error("the request must be an object".into()),
+            Err(e) => error(e.to_string()),
+        },
+        ("POST", "/control") => match serde_json::from_slice(&request.body) {
+            Ok(request) => answer_request(request, &state.control),
+            Err(e) => error(e.to_string()),
+        },
+        ("OPTIONS", _) => {
+            let _ = respond(&mut stream, 204, origin, None);
+            return;
+        }
+        (_, path) => {
+            let _ = respond(
+                &mut stream,
+                404,
+                origin,
+                Some(&error(format!("no {}", path))),
+            );
+            return;
+        }
+    };
+    let code = match response.get("ok") {
+        Some(Value::Bool(true)) => 200,
+        _ => 400,
+    };
+    let _ = respond(&mut stream, code, origin, Some(&response));
+}
+
+/// Sends the status, then every event, and answers the requests sent on the WebSocket.
+fn serve_websocket(mut stream: TcpStream, key: &str, state: &HttpState) {
+    let events = state.control.events.subscribe();
+    let head = format!(
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        derive_accept_key(key.trim().as_bytes())
+    );
+    if stream.write_all(head.as_bytes()).is_err() {
+        return;
+    }
+    // Reads wait a little at most, so events are sent in between.
+    let _ = stream.set_read_timeout(Some(Duration::from_millis(50)));
+    let mut socket = WebSocket::from_raw_socket(stream, Role::Server, None);
+
+    let mut first = Map::new();
+    first.insert("event".into(), "status".into());
+    if let Value::Object(status) = status_value(state) {
+        first.extend(status);
+    }
+    if socket
+        .send(Message::Text(Value::Object(first).to_string()))
+        .is_err()
+    {
+        return;
+    }
+
+    loop {
+        match socket.read() {
+            Ok(Message::Text(text)) => {
+                let response = match serde_json::from_str(&text) {
+                    Ok(request) => answer_request(request, &state.control),
+                    Err(e) => error(e.to_string()),
+                };
+                if socket.send(Message::Text(response.to_string())).is_err() {
+                    return;
+                }
+            }
+            Ok(_) => {}
+            Err(tungstenite::Error::Io(e))
+                if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(_) => return,
+        }
+        for event in events.try_iter() {
+            let text = serde_json::to_string(&event).unwrap();
+            if socket.send(Message::Text(text)).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// The status of the control socket with the last action.
+fn status_value(state: &HttpState) -> Value {
+    let mut status = status(&state.control);
+    let last_action = state.last_action.lock().unwrap().clone();
+    status.insert("last_action".into(), last_action.unwrap_or(Value::Null));
+    Value::Object(status)
+}
+
+fn error(message: String) -> Value {
+    json!({ "ok": false, "error": message })
+}
+
+fn read_request(stream: &mut TcpStream) -> Result<HttpRequest, String> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0; 4096];
+    loop {
+        let mut headers = [httparse::EMPTY_HEADER; 32];
+        let mut parsed = httparse::Request::new(&mut headers);
+        match parsed.parse(&buffer).map_err(|e| e.to_string())? {
+            httparse::Status::Complete(length) => {
+                let header = |name: &str| {
+                    parsed
+                        .headers
+                        .iter()
+                        .find(|header| header.name.eq_ignore_ascii_case(name))
+                        .and_then(|header| std::str::from_utf8(header.value).ok())
+                        .map(|value| value.trim().to_string())
+                };
+                let content_length = match header("content-length") {
+                    Some(length) => length.parse().map_err(|_| "bad Content-Length")?,
+                    None => 0,
+                };
+                if length + content_length > MAX_REQUEST {
+                    return Err("the request is too large".into());
+                }
+
+                let mut request = HttpRequest {
+                    method: parsed.method.unwrap_or_default().to_string(),
+                    path: parsed
+                        .path
+                        .unwrap_or_default()
+                        .split('?')
+                        .next()
+                        .unwrap_or_default()
+                        .to_string(),
+                    host: header("host"),
+                    origin: header("origin"),
+                    upgrade: header("upgrade"),
+                    connection: header("connection"),
+                    websocket_key: header("sec-websocket-key"),
+                    body: buffer[length..].to_vec(),
+                };
+                while request.body.len() < content_length {
+                    let read = stream.read(&mut chunk).map_err(|e| e.to_string())?;
+                    if read == 0 {
+                        return Err("the request was cut off".into());
+                    }
+                    request.body.extend_from_slice(&chunk[..read]);
+                }
+                request.body.truncate(content_length);
+                return Ok(request);
+            }
+            httparse::Status::Partial if buffer.len() > MAX_REQUEST => {
+                return Err("the request is too large".into())
+            }
+            httparse::Status::Partial => {}
+        }
+
+        let read = stream.read(&mut chunk).map_err(|e| e.to_string())?;
+        if read == 0 {
+            return Err("the request was cut off".into());
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    }
+}
+
+/// Writes a response with a JSON body, allowing `origin` to read it.
+fn respond(
+    stream: &mut TcpStream,
+    code: u16,
+    origin: Option<&str>,
+    body: Option<&Value>,
+) -> std::io::Result<()> {
+    let reason = match code {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        _ => "Not Found",
+    };
+    let body = body.map(|body| body.to_string() + "\n").unwrap_or_default();
+    let mut head = format!("HTTP/1.1 {} {}\r\nConnection: close\r\n", code, reason);
+    if let Some(origin) = origin {
+        head += &format!(
+            "Access-Control-Allow-Origin: {}\r\nAccess-Control-Allow-Methods: GET, POST\r\nAccess-Control-Allow-Headers: Content-Type\r\nVary: Origin\r\n",
+            origin
+        );
+    }
+    if !body.is_empty() {
+        head += "Content-Type: application/json\r\n";
+    }
+    head += &format!("Content-Length: {}\r\n\r\n", body.len());
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(body.as_bytes())?;
+    stream.flush()
+}
+
+/// Pages of other sites, and of sites whose name leads to 127.0.0.1, can't use joyshop.
+/// Neither can pages with the `null` origin, which any site can make with a sandboxed frame.
+fn is_local(request: &HttpRequest) -> bool {
+    let local_host = request.host.as_deref().map_or(true, is_loopback);
+    let local_origin = request.origin.as_deref().map_or(true, is_local_origin);
+    local_host && local_origin
+}
+
+/// Whether the request asks to switch to WebSocket, with `Upgrade: websocket` and
+/// `Connection: Upgrade`, which can be one of a list like `keep-alive, Upgrade`.
+fn is_websocket_upgrade(request: &HttpRequest) -> bool {
+    let upgrade = request
+        .upgrade
+        .as_deref()
+        .is_some_and(|upgrade| upgrade.eq_ignore_ascii_case("websocket"));
+    let connection = request.connection.as_deref().is_some_and(|connection| {
+        connection
+            .split(',')
+            .any(|option| option.trim().eq_ignore_ascii_case("upgrade"))
+    });
+    upgrade && connection
+}
+
+/// Whether a `Host` is this computer, like `localhost:8765`.
+fn is_loopback(host: &str) -> bool {
+    let name = match host.rsplit_once(':') {
+        Some((name, port)) if port.chars().all(|c| c.is_ascii_digit()) => name,
+        _ => host,
+    };
+    matches!(name, "localhost" | "127.0.0.1" | "[::1]")
+}
+
+fn is_local_origin(origin: &str) -> bool {
+    origin
+        .strip_prefix("http://")
+        .or_else(|| origin.strip_prefix("https://"))
+        .is_some_and(is_loopback)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::create_default;
+    use crate::events::EventBus;
+    use std::sync::RwLock;
+
+    fn request(headers: &[(&str, &str)]) -> HttpRequest {
+        let header = |name: &str| {
+            headers
+                .iter()
+                .find(|(header, _)| *header == name)
+                .map(|(_, value)| value.to_string())
+        };
+        HttpRequest {
+            method: "GET".into(),
+            path: "/events".into(),
+            host: header("host"),
+            origin: header("origin"),
+            upgrade: header("upgrade"),
+            connection: header("connection"),
+            websocket_key: header("sec-websocket-key"),
+            body: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn only_pages_on_this_computer_are_local() {
+        let is = |headers: &[(&str, &str)]| is_local(&request(headers));
+        assert!(is(&[]));
+        assert!(is(&[("host", "127.0.0.1:8765")]));
+        assert!(is(&[
+            ("host", "localhost:8765"),
+            ("origin", "http://localhost:3000")
+        ]));
+        assert!(is(&[("host", "[::1]:8765")]));
+        assert!(!is(&[("host", "127.0.0.1:8765"), ("origin", "null")]));
+        assert!(!is(&[
+            ("host", "127.0.0.1:8765"),
+            ("origin", "https://example.com")
+        ]));
+        assert!(!is(&[
+            ("host", "rebound.example.com:8765"),
+            ("origin", "null")
+        ]));
+    }
+
+    #[test]
+    fn websockets_need_the_upgrade_headers() {
+        let is = |headers: &[(&str, &str)]| is_websocket_upgrade(&request(headers));
+        assert!(is(&[("upgrade", "websocket"), ("connection", "Upgrade")]));
+        assert!(is(&[
+            ("upgrade", "WebSocket"),
+            ("connection", "keep-alive, upgrade")
+        ]));
+        assert!(!is(&[("connection", "Upgrade")]));
+        assert!(!is(&[("upgrade", "h2c"), ("connection", "Upgrade")]));
+        assert!(!is(&[
+            ("upgrade", "websocket"),
+            ("connection", "keep-alive")
+        ]));
+    }
+
+    /// Sends `request` to a server with the default config and gives its response.
+    fn exchange(request: &str) -> String {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let address = listener.local_addr().unwrap();
+        let state = HttpState {
+            control: ControlState {
+                config: Arc::new(RwLock::new(Box::new(create_default()))),
+                config_path: None,
+                events: EventBus::new(),
+                start_profile: Arc::new(Mutex::new(None)),
+            },
+            last_action: Arc::new(Mutex::new(None)),
+        };
+        let server = spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            handle_connection(stream, &state);
+        });
+
+        let mut client = TcpStream::connect(address).unwrap();
+        client.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        server.join().unwrap();
+        response
+    }
+
+    #[test]
+    fn events_without_an_upgrade_are_a_bad_request() {
+        let response = exchange("GET /events HTTP/1.1\r\nHost: 127.0.0.1\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n");
+        assert!(
+            response.starts_with("HTTP/1.1 400 Bad Request\r\n"),
+            "{}",
+            response
+        );
+        assert!(response.ends_with("{\"ok\":false,\"error\":\"/events is a WebSocket\"}\n"));
+    }
+
+    #[test]
+    fn requests_from_the_null_origin_are_forbidden() {
+        let body = r#"{"command": "reload_config"}"#;
+        let response = exchange(&format!(
+            "POST /control HTTP/1.1\r\nHost: 127.0.0.1\r\nOrigin: null\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        ));
+
+        assert!(
+            response.starts_with("HTTP/1.1 403 Forbidden\r\n"),
+            "{}",
+            response
+        );
+    }
+}
@@ -1,40 +1,117 @@
+use crate::configuration::{AnalogInput, BatteryLevel};
 use joycon_rs::joycon::input_report_mode::standard_full_mode::IMUData;
+use joycon_rs::joycon::input_report_mode::BatteryLevel as ReportedBatteryLevel;
 use joycon_rs::joycon::input_report_mode::{AnalogStickData, StandardInputReport};
-use joycon_rs::joycon::Buttons;
+use joycon_rs::joycon::{Buttons, JoyConDeviceType};
 use std::f64::consts::PI;
 
-pub fn is_button_down(
-    last_state: &StandardInputReport<IMUData>,
-    state: &StandardInputReport<IMUData>,
-    is_left: bool,
-    button: Buttons,
-) -> bool {
-    !is_button_press(last_state, is_left, button) && is_button_press(state, is_left, button)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Side {
+    Left,
+    Right,
 }
 
-pub fn is_button_up(
-    last_state: &StandardInputReport<IMUData>,
-    state: &StandardInputReport<IMUData>,
-    is_left: bool,
-    button: Buttons,
-) -> bool {
-    is_button_press(last_state, is_left, button) && !is_button_press(state, is_left, button)
+/// What one half of a controller reports, independent of the device it came from.
+#[derive(Clone, Debug)]
+pub struct SideInput {
+    pub buttons: Vec<Buttons>,
+    pub stick: AnalogStickData,
+    /// `None` for devices that don't report their battery.
+    pub battery: Option<BatteryLevel>,
+    /// `None` for devices without an IMU.
+    pub motion: Option<Motion>,
+}
+
+/// What the IMU of a device measured, averaged over the samples of a report.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Motion {
+    /// Degrees per second around x, y and z.
+    pub gyro: [f32; 3],
+    /// G along x, y and z.
+    pub accel: [f32; 3],
 }
 
-pub fn is_button_press(
+impl From<&IMUData> for Motion {
+    /// Scaled for the default sensitivity joycon-rs sets, ±2000 dps and ±8 G.
+    fn from(imu: &IMUData) -> Self {
+        const GYRO_SCALE: f32 = 2000.0 / 32767.0;
+        const ACCEL_SCALE: f32 = 8.0 / 32767.0;
+
+        let mut motion = Motion::default();
+        for sample in imu.data.iter() {
+            let gyro = [sample.gyro_1, sample.gyro_2, sample.gyro_3];
+            let accel = [sample.accel_x, sample.accel_y, sample.accel_z];
+            for axis in 0..3 {
+                motion.gyro[axis] += gyro[axis] as f32 * GYRO_SCALE / imu.data.len() as f32;
+                motion.accel[axis] += accel[axis] as f32 * ACCEL_SCALE / imu.data.len() as f32;
+            }
+        }
+        motion
+    }
+}
+
+pub fn device_sides(device_type: &JoyConDeviceType) -> &'static [Side] {
+    match device_type {
+        JoyConDeviceType::JoyConL => &[Side::Left],
+        JoyConDeviceType::JoyConR => &[Side::Right],
+        JoyConDeviceType::ProCon => &[Side::Left, Side::Right],
+    }
+}
+
+pub fn split_report(
+    device_type: &JoyConDeviceType,
     state: &StandardInputReport<IMUData>,
-    is_left: bool,
-    button: Buttons,
-) -> bool {
-    if is_left {
-        state.common.pushed_buttons.left.contains(&button)
-            || state.common.pushed_buttons.shared.contains(&button)
-    } else {
-        state.common.pushed_buttons.right.contains(&button)
-            || state.common.pushed_buttons.shared.contains(&button)
+) -> Vec<(Side, SideInput)> {
+    let buttons = &state.common.pushed_buttons;
+    device_sides(device_type)
+        .iter()
+        .map(|&side| {
+            let (side_buttons, stick) = match side {
+                Side::Left => (&buttons.left, &state.common.left_analog_stick_data),
+                Side::Right => (&buttons.right, &state.common.right_analog_stick_data),
+            };
+            let input = SideInput {
+                buttons: side_buttons
+                    .iter()
+                    .chain(&buttons.shared)
+                    .copied()
+                    .collect(),
+                stick: stick.clone(),
+                battery: Some(state.common.battery.level.into()),
+                motion: Some((&state.extra).into()),
+            };
+            (side, input)
+        })
+        .collect()
+}
+
+/// Values of the axes of an analog input, `None` when the device doesn't have it.
+pub fn analog_values(analog: AnalogInput, input: &SideInput) -> Option<Vec<f32>> {
+    match analog {
+        AnalogInput::Stick => {
+            let axis = |value: u16| ((value as f32 - 2048.0) / 2048.0).clamp(-1.0, 1.0);
+            Some(vec![
+                axis(input.stick.horizontal),
+                axis(input.stick.vertical),
+            ])
+        }
+        AnalogInput::Gyro => input.motion.map(|motion| motion.gyro.to_vec()),
+        AnalogInput::Accel => input.motion.map(|motion| motion.accel.to_vec()),
     }
 }
 
+pub fn is_button_down(last_input: &SideInput, input: &SideInput, button: Buttons) -> bool {
+    !is_button_press(last_input, button) && is_button_press(input, button)
+}
+
+pub fn is_button_up(last_input: &SideInput, input: &SideInput, button: Buttons) -> bool {
+    is_button_press(last_input, button) && !is_button_press(input, button)
+}
+
+pub fn is_button_press(input: &SideInput, button: Buttons) -> bool {
+    input.buttons.contains(&button)
+}
+
 pub fn recognize_stick_slot(
     slot_count: usize,
     deg_offset: i32,
@@ -80,3 +157,108 @@ pub fn recognize_stick_slot(
 
     return None;
 }
+
+impl From<ReportedBatteryLevel> for BatteryLevel {
+    fn from(level: ReportedBatteryLevel) -> Self {
+        match level {
+            ReportedBatteryLevel::Empty => BatteryLevel::Empty,
+            ReportedBatteryLevel::Critical => BatteryLevel::Critical,
+            ReportedBatteryLevel::Low => BatteryLevel::Low,
+            ReportedBatteryLevel::Medium => BatteryLevel::Medium,
+            ReportedBatteryLevel::Full => BatteryLevel::Full,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use joycon_rs::joycon::input_report_mode::standard_full_mode::AxisData;
+    use joycon_rs::joycon::input_report_mode::{
+        Battery, CommonReport, ConnectionInfo, Device, PushedButtons,
+    };
+
+    fn report(buttons: PushedButtons) -> StandardInputReport<IMUData> {
+        let stick = |horizontal, vertical| AnalogStickData {
+            horizontal,
+            vertical,
+        };
+        let axis = AxisData {
+            accel_x: 4096,
+            accel_y: 0,
+            accel_z: 0,
+            gyro_1: 0,
+            gyro_2: 16384,
+            gyro_3: 0,
+        };
+        StandardInputReport {
+            common: CommonReport {
+                input_report_id: 0x30,
+                timer: 0,
+                battery: Battery {
+                    level: ReportedBatteryLevel::Medium,
+                    is_charging: false,
+                },
+                connection_info: ConnectionInfo {
+                    device: Device::JoyCon,
+                    is_powered: false,
+                },
+                pushed_buttons: buttons,
+                left_analog_stick_data: stick(100, 2048),
+                right_analog_stick_data: stick(4000, 2048),
+                vibrator_input_report: 0,
+            },
+            extra: IMUData { data: [axis; 3] },
+        }
+    }
+
+    #[test]
+    fn joycons_report_their_own_side() {
+        let buttons = PushedButtons {
+            right: Vec::new(),
+            shared: vec![Buttons::Minus],
+            left: vec![Buttons::ZL],
+        };
+        let inputs = split_report(&JoyConDeviceType::JoyConL, &report(buttons));
+
+        assert_eq!(inputs.len(), 1);
+        let (side, input) = &inputs[0];
+        assert_eq!(*side, Side::Left);
+        assert_eq!(input.buttons, vec![Buttons::ZL, Buttons::Minus]);
+        assert_eq!(input.stick.horizontal, 100);
+        assert_eq!(input.battery, Some(BatteryLevel::Medium));
+    }
+
+    #[test]
+    fn pro_controllers_report_both_sides() {
+        let buttons = PushedButtons {
+            right: vec![Buttons::A],
+            shared: vec![Buttons::Home],
+            left: vec![Buttons::ZL],
+        };
+        let inputs = split_report(&JoyConDeviceType::ProCon, &report(buttons));
+
+        let sides = inputs.iter().map(|(side, _)| *side).collect::<Vec<_>>();
+        assert_eq!(sides, vec![Side::Left, Side::Right]);
+        assert_eq!(inputs[0].1.buttons, vec![Buttons::ZL, Buttons::Home]);
+        assert_eq!(inputs[1].1.buttons, vec![Buttons::A, Buttons::Home]);
+        assert_eq!(inputs[1].1.stick.horizontal, 4000);
+    }
+
+    #[test]
+    fn pro_controllers_report_their_motion_on_both_sides() {
+        let buttons = PushedButtons {
+            right: Vec::new(),
+            shared: Vec::new(),
+            left: Vec::new(),
+        };
+        let inputs = split_report(&JoyConDeviceType::ProCon, &report(buttons));
+
+        for (_, input) in inputs.iter() {
+            let gyro = analog_values(AnalogInput::Gyro, input).unwrap();
+            let accel = analog_values(AnalogInput::Accel, input).unwrap();
+            assert!((gyro[1] - 1000.0).abs() < 0.1);
+            assert!((accel[0] - 1.0).abs() < 0.001);
+        }
+    }
+}
@@ -1,45 +1,108 @@
-use crate::battery_light::get_light_states;
-use crate::configuration::{Config, KeyAction};
-use crate::input_recognizer::{is_button_down, is_button_up, recognize_stick_slot};
-use crate::key_sender::send_ev;
-use ::crossbeam_channel::Sender;
-use joycon_rs::joycon::input_report_mode::standard_full_mode::IMUData;
-use joycon_rs::joycon::input_report_mode::StandardInputReport;
+use crate::battery_monitor::BatteryMonitor;
+use crate::configuration::{Config, CONNECT_HAPTIC, LOW_BATTERY_HAPTIC};
+use crate::controller::{handle_controller, ControllerMessage, DeviceCommand};
+use crate::events::{Event, EventBus};
+use crate::haptics::{resolve_preset, HapticScheduler, Overlap};
+use crate::input_recognizer::{device_sides, split_report, Side};
+use crate::player_lights::set_light_states;
+use ::crossbeam_channel::{unbounded, Sender};
 use joycon_rs::joycon::joycon_features::JoyConFeature;
-use joycon_rs::prelude::lights::*;
 use joycon_rs::prelude::*;
-use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard};
+use once_cell::sync::Lazy;
+use std::sync::{Arc, Mutex, RwLock};
 use std::thread::sleep;
 use std::time::Instant;
 
-pub fn run_joyshop(config: Arc<RwLock<Box<Config>>>, tx: Sender<String>) {
+struct ControllerEntry {
+    id: usize,
+    sides: Vec<Side>,
+    messages: Sender<ControllerMessage>,
+}
+
+/// Logical controllers that devices can join, e.g. a JoyCon (L) waiting for its (R).
+static CONTROLLERS: Lazy<Mutex<Vec<ControllerEntry>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+pub fn run_joyshop(config: Arc<RwLock<Box<Config>>>, events: EventBus) {
     let manager = JoyConManager::get_instance();
     let new_device_receiver = match manager.lock() {
         Ok(manager) => manager.new_devices(),
         Err(_) => return,
     };
 
-    new_device_receiver.iter().for_each(|device| {
-        println!("Joycon Connected");
+    for (device_id, device) in new_device_receiver.iter().enumerate() {
+        let device_type = match device.lock() {
+            Ok(device) => device.device_type(),
+            Err(_) => continue,
+        };
+        println!("{:?} Connected", device_type);
 
-        let mut driver = create_driver(&device);
-        rumble_for_connect(&mut driver);
+        let driver = create_driver(&device);
         let joycon = StandardFullMode::new(driver).unwrap();
+        let sides = device_sides(&device_type);
+        let (controller_id, controller) = join_controller(sides, &config, &events);
         let config = config.clone();
-        let tx = tx.clone();
-        std::thread::spawn(move || handle_joycon_input(joycon, config, tx));
+        let events = events.clone();
+        std::thread::spawn(move || {
+            handle_device_input(joycon, device_id, device_type, controller, config, events);
+            leave_controller(controller_id, sides);
+        });
+    }
+}
+
+/// Adds a device to a controller missing its sides, or to a new controller.
+pub fn join_controller(
+    sides: &[Side],
+    config: &Arc<RwLock<Box<Config>>>,
+    events: &EventBus,
+) -> (usize, Sender<ControllerMessage>) {
+    join(&mut CONTROLLERS.lock().unwrap(), sides, || {
+        let (messages_tx, messages_rx) = unbounded();
+        let config = config.clone();
+        let events = events.clone();
+        std::thread::spawn(move || handle_controller(messages_rx, config, events));
+        messages_tx
+    })
+}
+
+pub fn leave_controller(id: usize, sides: &[Side]) {
+    leave(&mut CONTROLLERS.lock().unwrap(), id, sides);
+}
+
+/// Joins `sides` to a controller of `controllers`, or to a new one that `start` runs.
+fn join(
+    controllers: &mut Vec<ControllerEntry>,
+    sides: &[Side],
+    start: impl FnOnce() -> Sender<ControllerMessage>,
+) -> (usize, Sender<ControllerMessage>) {
+    let partner = controllers.iter_mut().find(|c| {
+        sides.len() == 1 && c.sides.len() == 1 && !c.sides.iter().any(|s| sides.contains(s))
     });
+    if let Some(partner) = partner {
+        partner.sides.extend_from_slice(sides);
+        return (partner.id, partner.messages.clone());
+    }
+
+    let id = controllers.iter().map(|c| c.id + 1).max().unwrap_or(0);
+    let messages = start();
+    controllers.push(ControllerEntry {
+        id,
+        sides: sides.to_vec(),
+        messages: messages.clone(),
+    });
+    (id, messages)
+}
+
+fn leave(controllers: &mut Vec<ControllerEntry>, id: usize, sides: &[Side]) {
+    if let Some(controller) = controllers.iter_mut().find(|c| c.id == id) {
+        controller.sides.retain(|s| !sides.contains(s));
+    }
+    controllers.retain(|c| !c.sides.is_empty());
 }
 
-fn rumble_for_connect(driver: &mut SimpleJoyConDriver) {
-    driver
-        .rumble((Some(Rumble::new(500.0, 1.0)), Some(Rumble::new(500.0, 1.0))))
-        .unwrap();
-    let now = Instant::now();
-    while now.elapsed().as_millis() < 500 {}
-    driver
-        .rumble((Some(Rumble::stop()), Some(Rumble::stop())))
-        .unwrap();
+pub fn send_to_controllers(message: impl Fn() -> ControllerMessage) {
+    for controller in CONTROLLERS.lock().unwrap().iter() {
+        let _ = controller.messages.send(message());
+    }
 }
 
 fn create_driver(device: &Arc<Mutex<JoyConDevice>>) -> SimpleJoyConDriver {
@@ -64,28 +127,44 @@ fn create_driver(device: &Arc<Mutex<JoyConDevice>>) -> SimpleJoyConDriver {
     }
 }
 
-fn handle_joycon_input(
+/// Reads one device and forwards its input to its controller.
+/// Rumble and lights are applied here, between reads, so they never stall input.
+fn handle_device_input(
     mut joycon: StandardFullMode<SimpleJoyConDriver>,
+    device_id: usize,
+    device_type: JoyConDeviceType,
+    controller: Sender<ControllerMessage>,
     config: Arc<RwLock<Box<Config>>>,
-    tx: Sender<String>,
+    events: EventBus,
 ) {
-    let mut last_state: StandardInputReport<IMUData> = joycon.read_input_report().unwrap();
-    let mut last_left_stick =
-        recognize_stick_slot(6, 0, None, &last_state.common.left_analog_stick_data);
-    let mut last_right_stick =
-        recognize_stick_slot(6, 0, None, &last_state.common.right_analog_stick_data);
+    let serial_number = joycon.driver().joycon().serial_number().to_string();
+    println!("{:?} serial number: {}", device_type, serial_number);
+
+    let (commands_tx, commands) = unbounded();
+    let connected = ControllerMessage::Connected {
+        device_id,
+        serial_number: serial_number.clone(),
+        sides: device_sides(&device_type),
+        commands: commands_tx,
+    };
+    if controller.send(connected).is_err() {
+        return;
+    }
 
-    let mut last_light_updated = Instant::now();
+    let mut battery = BatteryMonitor::new(&serial_number);
+    let mut last_battery = None;
+    let mut haptics = HapticScheduler::new();
+    if let Ok(config) = config.read() {
+        if let Some(segments) = resolve_preset(&config, CONNECT_HAPTIC) {
+            haptics.schedule(&segments, Overlap::Queue, Instant::now());
+        }
+    }
 
     loop {
-        let config = match config.read() {
-            Ok(v) => v,
-            Err(_) => continue,
-        };
         let state = match joycon.read_input_report() {
             Ok(s) => s,
             Err(e) => {
-                println!("Joycon error occurred: {:?}", e);
+                events.error(format!("Joycon error occurred: {:?}", e));
                 match e {
                     JoyConError::Disconnected => break,
                     _ => continue,
@@ -93,216 +172,94 @@ fn handle_joycon_input(
             }
         };
 
-        if last_light_updated.elapsed().as_secs() > 10 {
-            let (light, flash) = get_light_states(state.common.battery.level);
-            joycon.driver_mut().set_player_lights(light, flash).unwrap();
-            last_light_updated = Instant::now();
-        }
-
-        handle_button_action(&last_state, &state, &tx, true, Buttons::ZL, &config.zl);
-        handle_button_action(&last_state, &state, &tx, true, Buttons::L, &config.l);
-        handle_button_action(
-            &last_state,
-            &state,
-            &tx,
-            true,
-            Buttons::Minus,
-            &config.minus,
-        );
-        handle_button_action(
-            &last_state,
-            &state,
-            &tx,
-            true,
-            Buttons::LStick,
-            &config.left_stick,
-        );
-        handle_button_action(&last_state, &state, &tx, true, Buttons::Up, &config.up);
-        handle_button_action(&last_state, &state, &tx, true, Buttons::Down, &config.down);
-        handle_button_action(&last_state, &state, &tx, true, Buttons::Left, &config.left);
-        handle_button_action(
-            &last_state,
-            &state,
-            &tx,
-            true,
-            Buttons::Right,
-            &config.right,
-        );
-        handle_button_action(&last_state, &state, &tx, true, Buttons::SL, &config.left_sl);
-        handle_button_action(&last_state, &state, &tx, true, Buttons::SR, &config.left_sr);
-        handle_button_action(
-            &last_state,
-            &state,
-            &tx,
-            true,
-            Buttons::Capture,
-            &config.capture,
-        );
-
-        handle_button_action(&last_state, &state, &tx, false, Buttons::ZR, &config.zr);
-        handle_button_action(&last_state, &state, &tx, false, Buttons::R, &config.r);
-        handle_button_action(&last_state, &state, &tx, false, Buttons::Plus, &config.plus);
-        handle_button_action(
-            &last_state,
-            &state,
-            &tx,
-            false,
-            Buttons::RStick,
-            &config.right_stick,
-        );
-        handle_button_action(&last_state, &state, &tx, false, Buttons::A, &config.a);
-        handle_button_action(&last_state, &state, &tx, false, Buttons::B, &config.b);
-        handle_button_action(&last_state, &state, &tx, false, Buttons::X, &config.x);
-        handle_button_action(&last_state, &state, &tx, false, Buttons::Y, &config.y);
-        handle_button_action(&last_state, &state, &tx, false, Buttons::Home, &config.home);
-        handle_button_action(
-            &last_state,
-            &state,
-            &tx,
-            false,
-            Buttons::SL,
-            &config.right_sl,
-        );
-        handle_button_action(
-            &last_state,
-            &state,
-            &tx,
-            false,
-            Buttons::SR,
-            &config.right_sr,
-        );
-        handle_button_action(&last_state, &state, &tx, false, Buttons::Home, &config.home);
-
-        let left_stick =
-            recognize_stick_slot(6, 0, last_left_stick, &state.common.left_analog_stick_data);
-        handle_stick_action(
-            joycon.driver_mut(),
-            last_left_stick,
-            left_stick,
-            &tx,
-            &config,
-            true,
+        let battery_state = (
+            state.common.battery.level.into(),
+            state.common.battery.is_charging,
         );
+        if last_battery != Some(battery_state) {
+            last_battery = Some(battery_state);
+            events.publish(Event::BatteryChanged {
+                serial_number: serial_number.clone(),
+                level: battery_state.0,
+                is_charging: battery_state.1,
+            });
+        }
+        if let Some(alert) = battery.update(battery_state.0, battery_state.1) {
+            events.publish(Event::BatteryLow {
+                serial_number: serial_number.clone(),
+                level: alert,
+            });
+            if let Ok(config) = config.read() {
+                if let Some(segments) = resolve_preset(&config, LOW_BATTERY_HAPTIC) {
+                    haptics.schedule(&segments, Overlap::Queue, Instant::now());
+                }
+            }
+        }
 
-        let right_stick = recognize_stick_slot(
-            6,
-            0,
-            last_right_stick,
-            &state.common.right_analog_stick_data,
-        );
-        handle_stick_action(
-            joycon.driver_mut(),
-            last_right_stick,
-            right_stick,
-            &tx,
-            &config,
-            false,
-        );
+        let input = ControllerMessage::Input {
+            device_id,
+            inputs: split_report(&device_type, &state),
+        };
+        if controller.send(input).is_err() {
+            break;
+        }
 
-        last_state = state;
-        last_left_stick = left_stick;
-        last_right_stick = right_stick;
-    }
-}
+        for command in commands.try_iter() {
+            match command {
+                DeviceCommand::Haptic(segments, overlap) => {
+                    haptics.schedule(&segments, overlap, Instant::now());
+                }
+                DeviceCommand::Lights(light_states) => {
+                    if let Err(e) = set_light_states(joycon.driver_mut(), &light_states) {
+                        events.error(format!("JoyCon player lights error: {:?}", e));
+                    }
+                }
+            }
+        }
 
-fn handle_button_action(
-    last_state: &StandardInputReport<IMUData>,
-    state: &StandardInputReport<IMUData>,
-    tx: &Sender<String>,
-    is_left: bool,
-    button: Buttons,
-    action: &KeyAction,
-) {
-    if is_button_down(last_state, state, is_left, button) {
-        send_ev(action, true, tx);
+        if let Some(rumble) = haptics.tick(Instant::now()) {
+            if let Err(e) = joycon.driver_mut().rumble(rumble) {
+                events.error(format!("JoyCon rumble error: {:?}", e));
+            }
+        }
     }
 
-    if is_button_up(last_state, state, is_left, button) {
-        send_ev(action, false, tx);
-    }
+    let _ = controller.send(ControllerMessage::Disconnected { device_id });
 }
 
-fn handle_stick_action(
-    driver: &mut SimpleJoyConDriver,
-    last_stick: Option<usize>,
-    stick: Option<usize>,
-    tx: &Sender<String>,
-    config: &RwLockReadGuard<Box<Config>>,
-    is_left: bool,
-) {
-    if stick == last_stick {
-        return;
-    }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    if let Some(_) = stick {
-        rumble_for_stick_action(driver);
+    /// Joins a device to `controllers` without running a controller for it.
+    fn join_sides(controllers: &mut Vec<ControllerEntry>, sides: &[Side]) -> usize {
+        join(controllers, sides, || unbounded().0).0
     }
 
-    let top_right = if is_left {
-        &config.left_stick_top_right
-    } else {
-        &config.right_stick_top_right
-    };
-
-    let top = if is_left {
-        &config.left_stick_top_center
-    } else {
-        &config.right_stick_top_center
-    };
-
-    let top_left = if is_left {
-        &config.left_stick_top_left
-    } else {
-        &config.right_stick_top_left
-    };
-
-    let bottom_left = if is_left {
-        &config.left_stick_bottom_left
-    } else {
-        &config.right_stick_bottom_left
-    };
+    #[test]
+    fn joycons_pair_up_and_leave() {
+        let mut controllers = Vec::new();
 
-    let bottom = if is_left {
-        &config.left_stick_bottom_center
-    } else {
-        &config.right_stick_bottom_center
-    };
+        let left = join_sides(&mut controllers, &[Side::Left]);
+        let second_left = join_sides(&mut controllers, &[Side::Left]);
+        let right = join_sides(&mut controllers, &[Side::Right]);
+        let pro = join_sides(&mut controllers, &[Side::Left, Side::Right]);
+        assert_ne!(left, second_left);
+        assert_eq!(right, left);
+        assert!(pro != left && pro != second_left);
 
-    let bottom_right = if is_left {
-        &config.left_stick_bottom_right
-    } else {
-        &config.right_stick_bottom_right
-    };
-
-    match last_stick {
-        Some(0) => send_ev(top_right, false, &tx),
-        Some(1) => send_ev(top, false, &tx),
-        Some(2) => send_ev(top_left, false, &tx),
-        Some(3) => send_ev(bottom_left, false, &tx),
-        Some(4) => send_ev(bottom, false, &tx),
-        Some(5) => send_ev(bottom_right, false, &tx),
-        _ => {}
-    }
+        // A JoyCon (R) joins the JoyCon (L) left alone once its partner is gone.
+        leave(&mut controllers, right, &[Side::Right]);
+        let next_right = join_sides(&mut controllers, &[Side::Right]);
+        assert_eq!(next_right, left);
 
-    match stick {
-        Some(0) => send_ev(top_right, true, &tx),
-        Some(1) => send_ev(top, true, &tx),
-        Some(2) => send_ev(top_left, true, &tx),
-        Some(3) => send_ev(bottom_left, true, &tx),
-        Some(4) => send_ev(bottom, true, &tx),
-        Some(5) => send_ev(bottom_right, true, &tx),
-        _ => {}
+        for (id, sides) in [
+            (left, &[Side::Left, Side::Right][..]),
+            (second_left, &[Side::Left][..]),
+            (pro, &[Side::Left, Side::Right][..]),
+        ] {
+            leave(&mut controllers, id, sides);
+        }
+        assert!(controllers.is_empty());
     }
 }
-
-fn rumble_for_stick_action(driver: &mut SimpleJoyConDriver) {
-    driver
-        .rumble((Some(Rumble::new(100.0, 1.0)), Some(Rumble::new(100.0, 1.0))))
-        .unwrap();
-
-    let now = Instant::now();
-    while now.elapsed().as_millis() < 30 {}
-    driver
-        .rumble((Some(Rumble::stop()), Some(Rumble::stop())))
-        .unwrap();
-}
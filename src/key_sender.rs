@@ -1,20 +1,32 @@
 use crate::configuration::{Key, KeyAction};
-use crossbeam_channel::Sender;
+#[cfg(windows)]
 use std::convert::TryInto;
+#[cfg(windows)]
 use std::mem::{size_of, zeroed};
-use win_key_codes::{VK_CONTROL, VK_MENU, VK_SHIFT};
+#[cfg(windows)]
 use winapi::shared::windef::HWND;
+#[cfg(windows)]
 use winapi::um::winuser::{
-    GetForegroundWindow, SendInput, SendMessageW, INPUT, INPUT_KEYBOARD, KEYEVENTF_KEYUP,
-    WM_IME_CONTROL,
+    GetForegroundWindow, SendInput, SendMessageW, INPUT, INPUT_KEYBOARD, KEYEVENTF_EXTENDEDKEY,
+    KEYEVENTF_KEYUP, WM_IME_CONTROL,
 };
 
+#[cfg(windows)]
 extern "system" {
     fn ImmGetDefaultIMEWnd(hwnd: HWND) -> HWND;
 }
+#[cfg(windows)]
 const IMC_GETOPENSTATUS: usize = 0x06;
+#[cfg(windows)]
+const EXTENDED_KEYS: [Key; 4] = [
+    Key::RightControl,
+    Key::RightAlt,
+    Key::LeftWindows,
+    Key::RightWindows,
+];
 
-pub fn send_ev(key_action: &KeyAction, is_down: bool, tx: &Sender<String>) {
+pub fn send_ev(key_action: &KeyAction, is_down: bool) {
+    #[cfg(windows)]
     unsafe {
         let ime_window_handle = ImmGetDefaultIMEWnd(GetForegroundWindow());
         SendMessageW(ime_window_handle, WM_IME_CONTROL, IMC_GETOPENSTATUS, 0);
@@ -22,34 +34,28 @@ pub fn send_ev(key_action: &KeyAction, is_down: bool, tx: &Sender<String>) {
 
     if is_down {
         match key_action {
-            KeyAction::None => {}
+            KeyAction::None
+            | KeyAction::NextProfile
+            | KeyAction::SwitchProfile(_)
+            | KeyAction::ShowBatteryStatus
+            | KeyAction::Osc(_)
+            | KeyAction::OscStream(_)
+            | KeyAction::MidiNote(_)
+            | KeyAction::MidiCc(_)
+            | KeyAction::MidiProgram(_)
+            | KeyAction::MidiCcStream(_) => {}
             KeyAction::KeyHold(k) => {
-                if k.alt {
-                    send_input(VK_MENU, true);
-                }
-                if k.shift {
-                    send_input(VK_SHIFT, true);
-                }
-                if k.ctrl {
-                    send_input(VK_CONTROL, true);
+                for modifier in k.modifiers.iter() {
+                    send_input(*modifier as i32, true);
                 }
 
                 if k.key != Key::None {
                     send_input(k.key as i32, true);
                 }
-
-                println!("[Hold] {}", &k.name);
-                tx.send(k.name.clone()).unwrap();
             }
             KeyAction::KeyClick(k) => {
-                if k.alt {
-                    send_input(VK_MENU, true);
-                }
-                if k.shift {
-                    send_input(VK_SHIFT, true);
-                }
-                if k.ctrl {
-                    send_input(VK_CONTROL, true);
+                for modifier in k.modifiers.iter() {
+                    send_input(*modifier as i32, true);
                 }
 
                 if k.key != Key::None {
@@ -57,45 +63,42 @@ pub fn send_ev(key_action: &KeyAction, is_down: bool, tx: &Sender<String>) {
                     send_input(k.key as i32, false);
                 }
 
-                if k.alt {
-                    send_input(VK_MENU, false);
-                }
-                if k.shift {
-                    send_input(VK_SHIFT, false);
-                }
-                if k.ctrl {
-                    send_input(VK_CONTROL, false);
+                for modifier in k.modifiers.iter().rev() {
+                    send_input(*modifier as i32, false);
                 }
-
-                println!("[Click] {}", &k.name);
-                tx.send(k.name.clone()).unwrap();
             }
         }
     } else {
         match key_action {
-            KeyAction::None => {}
+            KeyAction::None
+            | KeyAction::NextProfile
+            | KeyAction::SwitchProfile(_)
+            | KeyAction::ShowBatteryStatus
+            | KeyAction::Osc(_)
+            | KeyAction::OscStream(_)
+            | KeyAction::MidiNote(_)
+            | KeyAction::MidiCc(_)
+            | KeyAction::MidiProgram(_)
+            | KeyAction::MidiCcStream(_) => {}
             KeyAction::KeyHold(k) => {
                 if k.key != Key::None {
                     send_input(k.key as i32, false);
                 }
 
-                if k.alt {
-                    send_input(VK_MENU, false);
-                }
-                if k.shift {
-                    send_input(VK_SHIFT, false);
-                }
-                if k.ctrl {
-                    send_input(VK_CONTROL, false);
+                for modifier in k.modifiers.iter().rev() {
+                    send_input(*modifier as i32, false);
                 }
-
-                println!("[Release] {}", &k.name);
             }
             KeyAction::KeyClick(_) => {}
         }
     }
 }
 
+/// Keys are only sent on Windows for now.
+#[cfg(not(windows))]
+fn send_input(_key: i32, _down: bool) {}
+
+#[cfg(windows)]
 fn send_input(key: i32, down: bool) {
     let mut input = unsafe { zeroed::<INPUT>() };
     input.type_ = INPUT_KEYBOARD;
@@ -107,6 +110,10 @@ fn send_input(key: i32, down: bool) {
     } else {
         ki.dwFlags = KEYEVENTF_KEYUP;
     }
+    // Without the flag, apps see the left key for right Ctrl and right Alt (AltGr).
+    if EXTENDED_KEYS.iter().any(|k| *k as i32 == key) {
+        ki.dwFlags |= KEYEVENTF_EXTENDEDKEY;
+    }
 
     let mut inputs = vec![input];
     unsafe {
@@ -1,23 +1,53 @@
 #![windows_subsystem = "windows"]
 
-use crate::configuration::load_config_or_default;
-use crate::joyshop::run_joyshop;
-use crossbeam_channel::unbounded;
-use std::thread::spawn;
+use crate::cli::{parse_args, run_command, USAGE};
 
-mod battery_light;
+mod battery_monitor;
+mod bundle;
+mod chord;
+mod cli;
+mod config_format;
+mod config_path;
 mod configuration;
+mod control;
+mod controller;
+mod events;
+mod extends;
+#[cfg(target_os = "linux")]
+mod gamepad;
+mod haptics;
+mod http_server;
 mod input_recognizer;
 mod joyshop;
 mod key_sender;
+mod midi;
+mod migration;
+mod mirror;
+#[cfg(windows)]
+mod named_pipe;
+mod osc;
+mod player_lights;
+mod presets;
+mod schema;
+#[cfg(windows)]
 mod ui;
+mod validation;
+#[cfg(windows)]
 mod window;
 
 fn main() {
-    let config = load_config_or_default();
-    let logic_config = config.clone();
-    let (tx, rx) = unbounded::<String>();
+    // The windows subsystem has no console of its own; print to the one joyshop was run from.
+    #[cfg(windows)]
+    unsafe {
+        winapi::um::wincon::AttachConsole(winapi::um::wincon::ATTACH_PARENT_PROCESS);
+    }
 
-    spawn(move || run_joyshop(logic_config, tx));
-    ui::process_ui(rx, config.read().unwrap().show_tooltip);
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
+    std::process::exit(run_command(options));
 }
@@ -0,0 +1,265 @@
+use crate::configuration::{AnalogInput, Config, KeyAction, MidiCcStream};
+use crate::controller::profile_actions;
+use crate::input_recognizer::{analog_values, SideInput};
+use midir::{MidiOutput, MidiOutputConnection};
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+const PORT_NAME: &str = "joyshop";
+
+enum Port {
+    Closed,
+    Open(MidiOutputConnection),
+    /// Opening it failed, which was reported. It isn't tried again.
+    Failed,
+}
+
+/// The MIDI output, opened on first use.
+static PORT: Lazy<Mutex<Port>> = Lazy::new(|| Mutex::new(Port::Closed));
+/// The last value streamed to each channel and controller, so only changes are sent.
+static STREAMED: Lazy<Mutex<HashMap<(u8, u8), u8>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Opens the MIDI output if a profile sends MIDI, so apps can find it before the first message.
+pub fn open_if_used(config: &Config) -> Result<(), String> {
+    let used = (0..config.profile_count())
+        .flat_map(|index| profile_actions(config.profile(index)))
+        .any(|action| {
+            matches!(
+                action,
+                KeyAction::MidiNote(_)
+                    | KeyAction::MidiCc(_)
+                    | KeyAction::MidiProgram(_)
+                    | KeyAction::MidiCcStream(_)
+            )
+        });
+    if !used {
+        return Ok(());
+    }
+    let mut port = PORT.lock().unwrap();
+    open_port(&mut port, config)
+}
+
+/// Sends the message of a MIDI action as its button goes down or up. Other actions send nothing.
+pub fn send_action(config: &Config, action: &KeyAction, is_down: bool) -> Result<(), String> {
+    match message(action, is_down) {
+        Some(message) => send(config, &message),
+        None => Ok(()),
+    }
+}
+
+/// The bytes of the message a MIDI action sends as its button goes down or up, if any.
+fn message(action: &KeyAction, is_down: bool) -> Option<Vec<u8>> {
+    let message = match (action, is_down) {
+        (KeyAction::MidiNote(note), true) => vec![
+            0x90 | channel(note.channel),
+            note.note & 0x7F,
+            note.velocity & 0x7F,
+        ],
+        (KeyAction::MidiNote(note), false) => {
+            vec![0x80 | channel(note.channel), note.note & 0x7F, 0]
+        }
+        (KeyAction::MidiCc(cc), true) => {
+            vec![
+                0xB0 | channel(cc.channel),
+                cc.controller & 0x7F,
+                cc.value & 0x7F,
+            ]
+        }
+        (KeyAction::MidiCc(cc), false) => vec![
+            0xB0 | channel(cc.channel),
+            cc.controller & 0x7F,
+            cc.release_value? & 0x7F,
+        ],
+        (KeyAction::MidiProgram(program), true) => {
+            vec![0xC0 | channel(program.channel), program.program & 0x7F]
+        }
+        _ => return None,
+    };
+    Some(message)
+}
+
+/// Sends the control changes of a `MidiCcStream` for one report of its side, when they changed.
+pub fn send_stream(
+    config: &Config,
+    stream: &MidiCcStream,
+    input: &SideInput,
+) -> Result<(), String> {
+    let values = match analog_values(stream.input, input) {
+        Some(values) => values,
+        None => return Ok(()),
+    };
+    let range = stream.range.unwrap_or(match stream.input {
+        AnalogInput::Stick => 1.0,
+        AnalogInput::Gyro => 360.0,
+        AnalogInput::Accel => 1.0,
+    });
+
+    let channel = channel(stream.channel);
+    let mut streamed = STREAMED.lock().unwrap();
+    for (&controller, value) in stream.controllers.iter().zip(values) {
+        let controller = controller & 0x7F;
+        let value = stream_value(value, range);
+        if streamed.insert((channel, controller), value) != Some(value) {
+            send(config, &[0xB0 | channel, controller, value])?;
+        }
+    }
+    Ok(())
+}
+
+/// The control change value of an analog value, with `range` as 127 and `-range` as 0.
+fn stream_value(value: f32, range: f32) -> u8 {
+    ((value / range + 1.0) / 2.0 * 127.0)
+        .round()
+        .clamp(0.0, 127.0) as u8
+}
+
+/// Names of the MIDI outputs there are, for `midi_port`.
+pub fn output_names() -> Vec<String> {
+    let output = match MidiOutput::new(PORT_NAME) {
+        Ok(output) => output,
+        Err(_) => return Vec::new(),
+    };
+    output
+        .ports()
+        .iter()
+        .filter_map(|port| output.port_name(port).ok())
+        .collect()
+}
+
+fn send(config: &Config, message: &[u8]) -> Result<(), String> {
+    let mut port = PORT.lock().unwrap();
+    open_port(&mut port, config)?;
+    match &mut *port {
+        Port::Open(connection) => connection
+            .send(message)
+            .map_err(|e| format!("couldn't send MIDI error: {}", e)),
+        _ => Ok(()),
+    }
+}
+
+fn open_port(port: &mut Port, config: &Config) -> Result<(), String> {
+    if let Port::Closed = port {
+        // Failed unless it opens, so it's only tried once.
+        *port = Port::Failed;
+        *port = Port::Open(connect(config)?);
+    }
+    Ok(())
+}
+
+/// Connects to `midi_port`, or makes the virtual port.
+fn connect(config: &Config) -> Result<MidiOutputConnection, String> {
+    let output =
+        MidiOutput::new(PORT_NAME).map_err(|e| format!("couldn't open MIDI error: {}", e))?;
+    let name = match &config.midi_port {
+        Some(name) => name,
+        None => return create_virtual(output),
+    };
+
+    let port = output
+        .ports()
+        .into_iter()
+        .find(|port| {
+            output
+                .port_name(port)
+                .is_ok_and(|n| n.contains(name.as_str()))
+        })
+        .ok_or_else(|| format!("there's no MIDI output called {}", name))?;
+    let full_name = output.port_name(&port).unwrap_or_else(|_| name.clone());
+    let connection = output
+        .connect(&port, PORT_NAME)
+        .map_err(|e| format!("couldn't connect to {} error: {}", full_name, e))?;
+    println!("[MIDI] {}", full_name);
+    Ok(connection)
+}
+
+#[cfg(unix)]
+fn create_virtual(output: MidiOutput) -> Result<MidiOutputConnection, String> {
+    use midir::os::unix::VirtualOutput;
+
+    let connection = output
+        .create_virtual(PORT_NAME)
+        .map_err(|e| format!("couldn't make the MIDI port error: {}", e))?;
+    println!("[MIDI] virtual port {}", PORT_NAME);
+    Ok(connection)
+}
+
+#[cfg(windows)]
+fn create_virtual(_output: MidiOutput) -> Result<MidiOutputConnection, String> {
+    Err("Windows can't make a virtual MIDI port, set midi_port to a port made with an app like loopMIDI".into())
+}
+
+/// The channel of a message, from the 1 to 16 of the settings.
+fn channel(channel: u8) -> u8 {
+    channel.clamp(1, 16) - 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::{MidiCc, MidiNote, MidiProgram};
+
+    #[test]
+    fn notes_go_on_and_off() {
+        let action = KeyAction::MidiNote(MidiNote {
+            note: 60,
+            velocity: 100,
+            channel: 2,
+        });
+        assert_eq!(message(&action, true), Some(vec![0x91, 60, 100]));
+        assert_eq!(message(&action, false), Some(vec![0x81, 60, 0]));
+    }
+
+    #[test]
+    fn control_changes_send_the_release_value_only_if_set() {
+        let mut cc = MidiCc {
+            controller: 64,
+            value: 127,
+            release_value: None,
+            channel: 1,
+        };
+        let action = KeyAction::MidiCc(cc.clone());
+        assert_eq!(message(&action, true), Some(vec![0xB0, 64, 127]));
+        assert_eq!(message(&action, false), None);
+
+        cc.release_value = Some(0);
+        let action = KeyAction::MidiCc(cc);
+        assert_eq!(message(&action, false), Some(vec![0xB0, 64, 0]));
+    }
+
+    #[test]
+    fn program_changes_are_sent_on_press_only() {
+        let action = KeyAction::MidiProgram(MidiProgram {
+            program: 5,
+            channel: 16,
+        });
+        assert_eq!(message(&action, true), Some(vec![0xCF, 5]));
+        assert_eq!(message(&action, false), None);
+    }
+
+    #[test]
+    fn channels_are_clamped_and_data_bytes_masked() {
+        let action = KeyAction::MidiNote(MidiNote {
+            note: 200,
+            velocity: 255,
+            channel: 0,
+        });
+        assert_eq!(message(&action, true), Some(vec![0x90, 200 & 0x7F, 0x7F]));
+
+        let action = KeyAction::MidiProgram(MidiProgram {
+            program: 128,
+            channel: 40,
+        });
+        assert_eq!(message(&action, true), Some(vec![0xCF, 0]));
+    }
+
+    #[test]
+    fn stream_values_span_the_range() {
+        assert_eq!(stream_value(-1.0, 1.0), 0);
+        assert_eq!(stream_value(0.0, 1.0), 64);
+        assert_eq!(stream_value(1.0, 1.0), 127);
+        assert_eq!(stream_value(180.0, 360.0), 95);
+        assert_eq!(stream_value(-720.0, 360.0), 0);
+        assert_eq!(stream_value(720.0, 360.0), 127);
+    }
+}
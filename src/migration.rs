@@ -0,0 +1,142 @@
+use crate::config_format::ConfigFormat;
+use serde_json::{Map, Value};
+use std::convert::TryFrom;
+use std::fs::{copy, read_to_string, write};
+use std::path::{Path, PathBuf};
+
+/// Version of the config format written by this joyshop.
+/// Bump it and add a step to `MIGRATIONS` when a setting is renamed or restructured.
+pub const CONFIG_VERSION: u32 = 1;
+
+type Migration = fn(&mut Map<String, Value>);
+
+/// `MIGRATIONS[n]` upgrades a config from version `n` to `n + 1`.
+const MIGRATIONS: [Migration; CONFIG_VERSION as usize] = [from_unversioned];
+
+/// Files from before versioning had the same settings, only without `version`.
+fn from_unversioned(_config: &mut Map<String, Value>) {}
+
+/// Upgrades a config to the current version.
+/// Returns the version it had, or `None` when it's already current.
+pub fn migrate(value: &mut Value) -> Result<Option<u32>, String> {
+    let config = match value.as_object_mut() {
+        Some(config) => config,
+        None => return Ok(None),
+    };
+
+    let version = match config.get("version") {
+        Some(version) => match version.as_u64().map(u32::try_from) {
+            Some(Ok(version)) => version,
+            _ => return Err(format!("version must be a number up to {}", u32::MAX)),
+        },
+        None => 0,
+    };
+    if version > CONFIG_VERSION {
+        return Err(format!(
+            "the config is from a newer joyshop (version {}, this one reads up to {})",
+            version, CONFIG_VERSION
+        ));
+    }
+    if version == CONFIG_VERSION {
+        return Ok(None);
+    }
+
+    for migration in MIGRATIONS[version as usize..].iter() {
+        migration(config);
+    }
+    config.insert("version".into(), CONFIG_VERSION.into());
+    Ok(Some(version))
+}
+
+/// Upgrades the config file at `path` in place, keeping the old one as a backup next to it.
+/// Returns whether the file was written. Files the migrations only add `version` to are left as
+/// they are, comments and all, since they are read the same way without it.
+pub fn migrate_file(path: &Path) -> Result<bool, String> {
+    let format = ConfigFormat::from_path(path)?;
+    let text = read_to_string(path).map_err(|e| format!("couldn't load file error: {}", e))?;
+    let original = format
+        .parse(&text)
+        .map_err(|(_, e)| format!("invalid {}: {}", format, e))?;
+
+    let mut value = original.clone();
+    let version = match migrate(&mut value)? {
+        Some(version) => version,
+        None => return Ok(false),
+    };
+    if without_version(&value) == without_version(&original) {
+        return Ok(false);
+    }
+
+    let backup = backup_path(path);
+    copy(path, &backup).map_err(|e| format!("couldn't back up the config error: {}", e))?;
+    write(path, format.write(&value)?)
+        .map_err(|e| format!("couldn't write the migrated config error: {}", e))?;
+
+    println!(
+        "[Config] {} migrated from version {} to {}, the old file is {}",
+        path.display(),
+        version,
+        CONFIG_VERSION,
+        backup.display()
+    );
+    Ok(true)
+}
+
+fn without_version(value: &Value) -> Value {
+    let mut value = value.clone();
+    if let Some(config) = value.as_object_mut() {
+        config.remove("version");
+    }
+    value
+}
+
+fn backup_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".bak");
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn unversioned_configs_get_the_current_version() {
+        let mut value = json!({ "show_tooltip": false });
+        assert_eq!(migrate(&mut value), Ok(Some(0)));
+        assert_eq!(
+            value,
+            json!({ "show_tooltip": false, "version": CONFIG_VERSION })
+        );
+        assert_eq!(migrate(&mut value), Ok(None));
+    }
+
+    #[test]
+    fn newer_and_broken_versions_are_refused() {
+        let mut newer = json!({ "version": CONFIG_VERSION + 1 });
+        assert!(migrate(&mut newer).unwrap_err().contains("newer joyshop"));
+        let mut broken = json!({ "version": "one" });
+        assert_eq!(
+            migrate(&mut broken),
+            Err("version must be a number up to 4294967295".to_string())
+        );
+        let mut too_large = json!({ "version": u64::from(u32::MAX) + 1 });
+        assert!(migrate(&mut too_large).is_err());
+    }
+
+    #[test]
+    fn files_that_only_lack_the_version_are_left_alone() {
+        let path =
+            std::env::temp_dir().join(format!("joyshop-migrate-{}.toml", std::process::id()));
+        let text = "# my settings\nshow_tooltip = false\n";
+        write(&path, text).unwrap();
+
+        let migrated = migrate_file(&path);
+        let after = read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(migrated, Ok(false));
+        assert_eq!(after, text);
+        assert!(!backup_path(&path).exists());
+    }
+}
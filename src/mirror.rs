@@ -0,0 +1,191 @@
+use crate::configuration::{Bindings, ButtonBindings, KeyAction, Mirror, MirrorMode, Profile};
+use std::mem::replace;
+
+/// Buttons of the left JoyCon and the buttons at the same place on the right one.
+/// The D-pad faces the ABXY buttons.
+const PAIRS: [(&str, &str); 17] = [
+    ("zl", "zr"),
+    ("l", "r"),
+    ("minus", "plus"),
+    ("left_stick", "right_stick"),
+    ("left_stick_top_left", "right_stick_top_left"),
+    ("left_stick_top_center", "right_stick_top_center"),
+    ("left_stick_top_right", "right_stick_top_right"),
+    ("left_stick_bottom_left", "right_stick_bottom_left"),
+    ("left_stick_bottom_center", "right_stick_bottom_center"),
+    ("left_stick_bottom_right", "right_stick_bottom_right"),
+    ("up", "x"),
+    ("down", "b"),
+    ("left", "y"),
+    ("right", "a"),
+    ("capture", "home"),
+    ("left_sl", "right_sl"),
+    ("left_sr", "right_sr"),
+];
+
+/// The pairs of buttons a mirror exchanges bindings between.
+/// Buttons it pairs differently lose their pair in `PAIRS`.
+pub fn mirror_pairs(mirror: &Mirror) -> Result<(MirrorMode, Vec<(String, String)>), String> {
+    let mut pairs = PAIRS
+        .iter()
+        .map(|(left, right)| (left.to_string(), right.to_string()))
+        .collect::<Vec<_>>();
+
+    let (mode, buttons) = match mirror {
+        Mirror::Mode(mode) => return Ok((*mode, pairs)),
+        Mirror::Custom { mode, buttons } => (*mode, buttons),
+    };
+    for (left, right) in buttons {
+        if !PAIRS.iter().any(|(button, _)| button == left) {
+            return Err(format!("`{}` isn't a button of the left JoyCon", left));
+        }
+        if !PAIRS.iter().any(|(_, button)| button == right) {
+            return Err(format!("`{}` isn't a button of the right JoyCon", right));
+        }
+        pairs.retain(|(l, r)| l != left && r != right);
+        pairs.push((left.clone(), right.clone()));
+    }
+    Ok((mode, pairs))
+}
+
+/// Applies the mirror of a profile to its JoyCon and Pro Controller bindings.
+/// Mirrors that `mirror_pairs` rejects were removed by validation, so they're ignored here.
+pub fn apply_mirror(profile: &mut Profile) {
+    let (mode, pairs) = match profile.mirror.as_ref().map(mirror_pairs) {
+        Some(Ok(mirror)) => mirror,
+        _ => return,
+    };
+
+    for (left, right) in pairs.iter() {
+        exchange(
+            &mut profile.bindings,
+            Bindings::binding_mut,
+            mode,
+            left,
+            right,
+        );
+        exchange(
+            &mut profile.pro_controller.buttons,
+            ButtonBindings::binding_mut,
+            mode,
+            left,
+            right,
+        );
+    }
+}
+
+/// `binding_mut` of `Bindings` or `ButtonBindings`.
+type BindingMut<T> = for<'a, 'b> fn(&'a mut T, &'b str) -> Option<&'a mut KeyAction>;
+
+fn exchange<T>(
+    bindings: &mut T,
+    binding: BindingMut<T>,
+    mode: MirrorMode,
+    left: &str,
+    right: &str,
+) {
+    let (from, to) = match mode {
+        MirrorMode::LeftToRight | MirrorMode::Swap => (left, right),
+        MirrorMode::RightToLeft => (right, left),
+    };
+    // The Pro Controller has no SL and SR.
+    let action = match binding(bindings, from) {
+        Some(action) => action.clone(),
+        None => return,
+    };
+    let replaced = match binding(bindings, to) {
+        Some(to) => replace(to, action),
+        None => return,
+    };
+    if mode == MirrorMode::Swap {
+        *binding(bindings, from).unwrap() = replaced;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::Key;
+
+    fn key(action: &KeyAction) -> Key {
+        match action {
+            KeyAction::KeyHold(k) | KeyAction::KeyClick(k) => k.key,
+            _ => panic!("{:?} has no key", action),
+        }
+    }
+
+    fn mirrored(mirror: Option<Mirror>) -> Profile {
+        let mut profile: Profile = serde_json::from_value(serde_json::json!({
+            "zl": {"KeyHold": {"name": "Eraser", "keys": "E"}},
+            "zr": {"KeyHold": {"name": "Brush", "keys": "B"}},
+            "pro_controller": {
+                "zl": {"KeyHold": {"name": "Eraser", "keys": "E"}},
+                "zr": {"KeyHold": {"name": "Brush", "keys": "B"}},
+            },
+        }))
+        .unwrap();
+        profile.mirror = mirror;
+        apply_mirror(&mut profile);
+        profile
+    }
+
+    fn triggers(profile: &Profile) -> [Key; 4] {
+        let joycon = &profile.bindings.buttons;
+        let pro_controller = &profile.pro_controller.buttons;
+        [
+            key(&joycon.zl),
+            key(&joycon.zr),
+            key(&pro_controller.zl),
+            key(&pro_controller.zr),
+        ]
+    }
+
+    #[test]
+    fn modes_copy_or_swap_the_sides() {
+        let mode = |mode| triggers(&mirrored(Some(Mirror::Mode(mode))));
+        assert_eq!(triggers(&mirrored(None)), [Key::E, Key::B, Key::E, Key::B]);
+        assert_eq!(
+            mode(MirrorMode::LeftToRight),
+            [Key::E, Key::E, Key::E, Key::E]
+        );
+        assert_eq!(
+            mode(MirrorMode::RightToLeft),
+            [Key::B, Key::B, Key::B, Key::B]
+        );
+        assert_eq!(mode(MirrorMode::Swap), [Key::B, Key::E, Key::B, Key::E]);
+    }
+
+    #[test]
+    fn custom_pairs_replace_the_ones_of_their_buttons() {
+        let mirror = Mirror::Custom {
+            mode: MirrorMode::Swap,
+            buttons: vec![("up".to_string(), "a".to_string())]
+                .into_iter()
+                .collect(),
+        };
+        let (_, pairs) = mirror_pairs(&mirror).unwrap();
+        let has = |left: &str, right: &str| pairs.iter().any(|(l, r)| l == left && r == right);
+        assert!(has("up", "a"));
+        assert!(!has("up", "x"));
+        assert!(!has("right", "a"));
+        assert!(has("zl", "zr"));
+    }
+
+    #[test]
+    fn custom_pairs_must_be_left_then_right() {
+        let mirror = |left: &str, right: &str| Mirror::Custom {
+            mode: MirrorMode::Swap,
+            buttons: vec![(left.to_string(), right.to_string())]
+                .into_iter()
+                .collect(),
+        };
+        assert_eq!(
+            mirror_pairs(&mirror("a", "up")).unwrap_err(),
+            "`a` isn't a button of the left JoyCon"
+        );
+        assert_eq!(
+            mirror_pairs(&mirror("up", "down")).unwrap_err(),
+            "`down` isn't a button of the right JoyCon"
+        );
+    }
+}
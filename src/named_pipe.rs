@@ -0,0 +1,126 @@
+use crate::window::to_unicode;
+use std::io::{Error, Read, Result, Write};
+use std::path::Path;
+use std::ptr::null_mut;
+use winapi::shared::minwindef::DWORD;
+use winapi::shared::winerror::{ERROR_BROKEN_PIPE, ERROR_PIPE_CONNECTED};
+use winapi::um::fileapi::{FlushFileBuffers, ReadFile, WriteFile};
+use winapi::um::handleapi::{CloseHandle, INVALID_HANDLE_VALUE};
+use winapi::um::namedpipeapi::{ConnectNamedPipe, CreateNamedPipeW, DisconnectNamedPipe};
+use winapi::um::winbase::{
+    FILE_FLAG_FIRST_PIPE_INSTANCE, PIPE_ACCESS_DUPLEX, PIPE_READMODE_BYTE,
+    PIPE_REJECT_REMOTE_CLIENTS, PIPE_TYPE_BYTE, PIPE_UNLIMITED_INSTANCES, PIPE_WAIT,
+};
+use winapi::um::winnt::HANDLE;
+
+const BUFFER_SIZE: DWORD = 4096;
+
+/// One instance of the server end of a named pipe, read and written as a byte stream.
+pub struct PipeStream {
+    handle: HANDLE,
+}
+
+// The handle is only used by the thread that owns the stream.
+unsafe impl Send for PipeStream {}
+
+impl PipeStream {
+    /// Makes an instance of the pipe at `path`. The `first` one fails when another
+    /// process has the pipe already. Clients on other machines are refused.
+    pub fn create(path: &Path, first: bool) -> Result<PipeStream> {
+        let name = to_unicode(&path.to_string_lossy());
+        let mut open_mode = PIPE_ACCESS_DUPLEX;
+        if first {
+            open_mode |= FILE_FLAG_FIRST_PIPE_INSTANCE;
+        }
+        let handle = unsafe {
+            CreateNamedPipeW(
+                name.as_ptr(),
+                open_mode,
+                PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+                PIPE_UNLIMITED_INSTANCES,
+                BUFFER_SIZE,
+                BUFFER_SIZE,
+                0,
+                null_mut(),
+            )
+        };
+        if handle == INVALID_HANDLE_VALUE {
+            return Err(Error::last_os_error());
+        }
+        Ok(PipeStream { handle })
+    }
+
+    /// Waits for a client.
+    pub fn connect(&self) -> Result<()> {
+        if unsafe { ConnectNamedPipe(self.handle, null_mut()) } != 0 {
+            return Ok(());
+        }
+        // A client that connected between `create` and here.
+        let error = Error::last_os_error();
+        match error.raw_os_error() {
+            Some(code) if code as DWORD == ERROR_PIPE_CONNECTED => Ok(()),
+            _ => Err(error),
+        }
+    }
+}
+
+impl Read for PipeStream {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let mut read = 0;
+        let length = buf.len().min(DWORD::MAX as usize) as DWORD;
+        let ok = unsafe {
+            ReadFile(
+                self.handle,
+                buf.as_mut_ptr() as *mut _,
+                length,
+                &mut read,
+                null_mut(),
+            )
+        };
+        if ok != 0 {
+            return Ok(read as usize);
+        }
+        // The client closed its end.
+        let error = Error::last_os_error();
+        match error.raw_os_error() {
+            Some(code) if code as DWORD == ERROR_BROKEN_PIPE => Ok(0),
+            _ => Err(error),
+        }
+    }
+}
+
+impl Write for PipeStream {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let mut written = 0;
+        let length = buf.len().min(DWORD::MAX as usize) as DWORD;
+        let ok = unsafe {
+            WriteFile(
+                self.handle,
+                buf.as_ptr() as *const _,
+                length,
+                &mut written,
+                null_mut(),
+            )
+        };
+        if ok == 0 {
+            return Err(Error::last_os_error());
+        }
+        Ok(written as usize)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if unsafe { FlushFileBuffers(self.handle) } == 0 {
+            return Err(Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+impl Drop for PipeStream {
+    fn drop(&mut self) {
+        unsafe {
+            DisconnectNamedPipe(self.handle);
+            CloseHandle(self.handle);
+        }
+    }
+}
@@ -0,0 +1,175 @@
+use crate::configuration::{Config, KeyAction, OscArg, OscStream};
+use crate::input_recognizer::{analog_values, SideInput};
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::Mutex;
+
+const DEFAULT_TARGET: &str = "127.0.0.1:9000";
+
+/// Targets by the text they're written as, so names are only looked up once.
+/// Targets that can't be resolved are `None`, and only reported the first time.
+static TARGETS: Lazy<Mutex<HashMap<String, Option<SocketAddr>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+static SOCKET_V4: Lazy<Option<UdpSocket>> =
+    Lazy::new(|| UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).ok());
+static SOCKET_V6: Lazy<Option<UdpSocket>> =
+    Lazy::new(|| UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0)).ok());
+
+/// Sends the message of an `Osc` action as its button goes down or up. Other actions send nothing.
+pub fn send_action(config: &Config, action: &KeyAction, is_down: bool) -> Result<(), String> {
+    let message = match action {
+        KeyAction::Osc(message) => message,
+        _ => return Ok(()),
+    };
+    let args = match (is_down, &message.release_args) {
+        (true, _) => &message.args,
+        (false, Some(args)) => args,
+        (false, None) => return Ok(()),
+    };
+    let target = message.target.as_deref().or(config.osc_target.as_deref());
+    send_osc(target.unwrap_or(DEFAULT_TARGET), &message.address, args)
+}
+
+/// Sends the input an `OscStream` action streams, for one report of its side.
+pub fn send_stream(config: &Config, stream: &OscStream, input: &SideInput) -> Result<(), String> {
+    let values = match analog_values(stream.input, input) {
+        Some(values) => values,
+        None => return Ok(()),
+    };
+    let args = values.into_iter().map(OscArg::Float).collect::<Vec<_>>();
+    let target = stream.target.as_deref().or(config.osc_target.as_deref());
+    send_osc(target.unwrap_or(DEFAULT_TARGET), &stream.address, &args)
+}
+
+/// Sends one OSC message over UDP to `target`, like `127.0.0.1:9000`.
+pub fn send_osc(target: &str, address: &str, args: &[OscArg]) -> Result<(), String> {
+    let target = {
+        let mut targets = TARGETS.lock().unwrap();
+        match targets.get(target) {
+            Some(Some(address)) => *address,
+            Some(None) => return Ok(()),
+            None => {
+                let resolved = resolve(target);
+                targets.insert(target.to_string(), resolved.as_ref().ok().copied());
+                resolved?
+            }
+        }
+    };
+
+    let socket = match target {
+        SocketAddr::V4(_) => SOCKET_V4.as_ref(),
+        SocketAddr::V6(_) => SOCKET_V6.as_ref(),
+    };
+    let socket = socket.ok_or("couldn't open a socket for OSC")?;
+    socket
+        .send_to(&encode_message(address, args), target)
+        .map(|_| ())
+        .map_err(|e| format!("couldn't send OSC to {} error: {}", target, e))
+}
+
+/// The first address of `target`, IPv4 ones first since OSC apps mostly listen on those.
+fn resolve(target: &str) -> Result<SocketAddr, String> {
+    let addresses = target
+        .to_socket_addrs()
+        .map_err(|e| format!("OSC target {} error: {}", target, e))?
+        .collect::<Vec<_>>();
+    addresses
+        .iter()
+        .find(|address| address.is_ipv4())
+        .or_else(|| addresses.first())
+        .copied()
+        .ok_or_else(|| format!("OSC target {} has no address", target))
+}
+
+fn encode_message(address: &str, args: &[OscArg]) -> Vec<u8> {
+    let mut packet = Vec::new();
+    push_string(&mut packet, address);
+    let tags = std::iter::once(',')
+        .chain(args.iter().map(|arg| match arg {
+            OscArg::Bool(true) => 'T',
+            OscArg::Bool(false) => 'F',
+            OscArg::Int(_) => 'i',
+            OscArg::Float(_) => 'f',
+            OscArg::String(_) => 's',
+        }))
+        .collect::<String>();
+    push_string(&mut packet, &tags);
+
+    for arg in args {
+        match arg {
+            OscArg::Bool(_) => {}
+            OscArg::Int(value) => packet.extend_from_slice(&value.to_be_bytes()),
+            OscArg::Float(value) => packet.extend_from_slice(&value.to_be_bytes()),
+            OscArg::String(value) => push_string(&mut packet, value),
+        }
+    }
+    packet
+}
+
+/// OSC strings end with a null and are padded with more to a multiple of 4 bytes.
+fn push_string(packet: &mut Vec<u8>, text: &str) {
+    packet.extend_from_slice(text.as_bytes());
+    packet.resize(packet.len() + 4 - text.len() % 4, 0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::{create_default, OscMessage};
+    use std::time::Duration;
+
+    #[test]
+    fn strings_are_padded_to_four_bytes() {
+        let padded = |text: &str| {
+            let mut packet = Vec::new();
+            push_string(&mut packet, text);
+            packet
+        };
+        assert_eq!(padded("/a"), b"/a\0\0");
+        assert_eq!(padded("/abc"), b"/abc\0\0\0\0");
+        assert_eq!(padded(","), b",\0\0\0");
+    }
+
+    #[test]
+    fn args_follow_their_type_tags() {
+        let args = [
+            OscArg::Int(1),
+            OscArg::Float(0.5),
+            OscArg::String("hi".into()),
+            OscArg::Bool(true),
+            OscArg::Bool(false),
+        ];
+        let mut expected = b"/layer/1\0\0\0\0,ifsTF\0\0".to_vec();
+        expected.extend_from_slice(&[0, 0, 0, 1]);
+        expected.extend_from_slice(&[0x3F, 0, 0, 0]);
+        expected.extend_from_slice(b"hi\0\0");
+        assert_eq!(encode_message("/layer/1", &args), expected);
+        assert_eq!(encode_message("/go", &[]), b"/go\0,\0\0\0");
+    }
+
+    #[test]
+    fn release_args_are_sent_when_the_button_is_released() {
+        let receiver = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let action = KeyAction::Osc(OscMessage {
+            address: "/record".into(),
+            args: vec![OscArg::Int(1)],
+            release_args: Some(vec![OscArg::Int(0)]),
+            target: Some(receiver.local_addr().unwrap().to_string()),
+        });
+        let config = create_default();
+
+        send_action(&config, &action, true).unwrap();
+        send_action(&config, &action, false).unwrap();
+        let mut packet = [0; 64];
+        let mut received = || {
+            let length = receiver.recv(&mut packet).unwrap();
+            packet[..length].to_vec()
+        };
+        assert_eq!(received(), encode_message("/record", &[OscArg::Int(1)]));
+        assert_eq!(received(), encode_message("/record", &[OscArg::Int(0)]));
+    }
+}
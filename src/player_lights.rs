@@ -0,0 +1,155 @@
+use crate::configuration::{BatteryLevel, BatteryLights, Config, PlayerLight, PlayerLightMode};
+use joycon_rs::joycon::lights::{Flash, LightUp, Lights};
+use joycon_rs::prelude::*;
+
+pub type LightStates = [PlayerLight; 4];
+
+const OFF: PlayerLight = PlayerLight::Off;
+const ON: PlayerLight = PlayerLight::On;
+
+/// The player indicator patterns of the Switch for players 1 to 8.
+const NUMBER_LIGHTS: [LightStates; 8] = [
+    [ON, OFF, OFF, OFF],
+    [ON, ON, OFF, OFF],
+    [ON, ON, ON, OFF],
+    [ON, ON, ON, ON],
+    [ON, OFF, OFF, ON],
+    [ON, OFF, ON, OFF],
+    [ON, OFF, ON, ON],
+    [OFF, ON, ON, OFF],
+];
+
+pub fn get_light_states(
+    config: &Config,
+    battery_level: BatteryLevel,
+    profile_index: usize,
+    slot: usize,
+) -> LightStates {
+    match &config.player_lights {
+        PlayerLightMode::Battery(thresholds) => battery_lights(thresholds, battery_level),
+        PlayerLightMode::Profile => number_lights(profile_index + 1),
+        PlayerLightMode::ControllerSlot => number_lights(slot + 1),
+        PlayerLightMode::Custom => match config.profile(profile_index).lights {
+            Some(lights) => lights,
+            None => number_lights(profile_index + 1),
+        },
+    }
+}
+
+pub fn set_light_states(driver: &mut SimpleJoyConDriver, states: &LightStates) -> JoyConResult<()> {
+    const LIGHT_UP: [LightUp; 4] = [LightUp::LED0, LightUp::LED1, LightUp::LED2, LightUp::LED3];
+    const FLASH: [Flash; 4] = [Flash::LED0, Flash::LED1, Flash::LED2, Flash::LED3];
+
+    let mut light_up = Vec::new();
+    let mut flash = Vec::new();
+    for (i, state) in states.iter().enumerate() {
+        match state {
+            PlayerLight::Off => {}
+            PlayerLight::On => light_up.push(LIGHT_UP[i]),
+            PlayerLight::Flash => flash.push(FLASH[i]),
+        }
+    }
+
+    driver.set_player_lights(&light_up, &flash).map(|_| ())
+}
+
+/// Fills the bar from the last LED, flashing every LED when the battery is empty.
+fn battery_lights(thresholds: &BatteryLights, level: BatteryLevel) -> LightStates {
+    let count = [
+        thresholds.one_light,
+        thresholds.two_lights,
+        thresholds.three_lights,
+        thresholds.four_lights,
+    ]
+    .iter()
+    .filter(|&&threshold| level >= threshold)
+    .count();
+
+    if count == 0 {
+        return [PlayerLight::Flash; 4];
+    }
+
+    let lit = if level <= thresholds.flash_at {
+        PlayerLight::Flash
+    } else {
+        PlayerLight::On
+    };
+
+    let mut states = [PlayerLight::Off; 4];
+    for state in states.iter_mut().skip(4 - count) {
+        *state = lit;
+    }
+    states
+}
+
+/// Numbers above 8 repeat the patterns, flashing.
+fn number_lights(number: usize) -> LightStates {
+    let index = (number.max(1) - 1) % NUMBER_LIGHTS.len();
+    let mut states = NUMBER_LIGHTS[index];
+    if number > NUMBER_LIGHTS.len() {
+        for state in states.iter_mut().filter(|s| **s == PlayerLight::On) {
+            *state = PlayerLight::Flash;
+        }
+    }
+    states
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FLASH: PlayerLight = PlayerLight::Flash;
+
+    #[test]
+    fn battery_fills_from_the_last_light() {
+        let thresholds = BatteryLights::default();
+        assert_eq!(
+            battery_lights(&thresholds, BatteryLevel::Full),
+            [ON, ON, ON, ON]
+        );
+        assert_eq!(
+            battery_lights(&thresholds, BatteryLevel::Low),
+            [OFF, OFF, ON, ON]
+        );
+        assert_eq!(
+            battery_lights(&thresholds, BatteryLevel::Critical),
+            [OFF, OFF, OFF, FLASH]
+        );
+        assert_eq!(battery_lights(&thresholds, BatteryLevel::Empty), [FLASH; 4]);
+    }
+
+    #[test]
+    fn numbers_repeat_flashing_after_eight() {
+        assert_eq!(number_lights(1), [ON, OFF, OFF, OFF]);
+        assert_eq!(number_lights(8), [OFF, ON, ON, OFF]);
+        assert_eq!(number_lights(9), [FLASH, OFF, OFF, OFF]);
+    }
+
+    #[test]
+    fn modes_pick_their_pattern() {
+        let mut config = Config::default();
+        config.profiles.push(config.profile.clone());
+        config.profiles[0].lights = Some([FLASH, OFF, OFF, FLASH]);
+
+        config.player_lights = PlayerLightMode::Profile;
+        assert_eq!(
+            get_light_states(&config, BatteryLevel::Full, 1, 0),
+            [ON, ON, OFF, OFF]
+        );
+        config.player_lights = PlayerLightMode::ControllerSlot;
+        assert_eq!(
+            get_light_states(&config, BatteryLevel::Full, 1, 2),
+            [ON, ON, ON, OFF]
+        );
+        config.player_lights = PlayerLightMode::Custom;
+        assert_eq!(
+            get_light_states(&config, BatteryLevel::Full, 1, 0),
+            [FLASH, OFF, OFF, FLASH]
+        );
+        // Profiles without lights show their number.
+        assert_eq!(
+            get_light_states(&config, BatteryLevel::Full, 0, 0),
+            [ON, OFF, OFF, OFF]
+        );
+    }
+}
@@ -0,0 +1,71 @@
+use crate::configuration::{create_default, inherit_base_bindings, Config};
+use serde_json::Value;
+
+/// The preset of the default config.
+const DEFAULT_PRESET: &str = "photoshop";
+
+/// Presets joyshop comes with besides the default one, as the settings files in `presets/`.
+const PRESET_FILES: [(&str, &str); 5] = [
+    ("krita", include_str!("../presets/krita.json")),
+    ("gimp", include_str!("../presets/gimp.json")),
+    (
+        "clip-studio-paint",
+        include_str!("../presets/clip-studio-paint.json"),
+    ),
+    ("blender", include_str!("../presets/blender.json")),
+    ("aseprite", include_str!("../presets/aseprite.json")),
+];
+
+/// The settings of the preset called `name`, ignoring case, as they're written in its file.
+pub fn preset_value(name: &str) -> Option<Value> {
+    if name.eq_ignore_ascii_case(DEFAULT_PRESET) {
+        return Some(serde_json::to_value(create_default()).unwrap());
+    }
+    PRESET_FILES
+        .iter()
+        .find(|(preset, _)| preset.eq_ignore_ascii_case(name))
+        .map(|(_, text)| serde_json::from_str(text).unwrap())
+}
+
+/// The preset called `name`, ignoring case.
+pub fn preset(name: &str) -> Option<Config> {
+    preset_value(name).map(|mut value| {
+        inherit_base_bindings(&mut value);
+        serde_json::from_value(value).unwrap()
+    })
+}
+
+pub fn preset_names() -> Vec<&'static str> {
+    std::iter::once(DEFAULT_PRESET)
+        .chain(PRESET_FILES.iter().map(|(name, _)| *name))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config_format::ConfigFormat;
+    use crate::validation::validate_config;
+
+    #[test]
+    fn every_preset_loads_without_problems() {
+        for name in preset_names() {
+            let text = serde_json::to_string_pretty(&preset_value(name).unwrap()).unwrap();
+            let (_, diagnostics) = validate_config(&text, ConfigFormat::Json, None);
+            let diagnostics = diagnostics
+                .iter()
+                .map(|d| d.to_string())
+                .collect::<Vec<_>>();
+            assert!(diagnostics.is_empty(), "{}: {:?}", name, diagnostics);
+            // Panics when the file doesn't read as a `Config`.
+            preset(name);
+        }
+    }
+
+    #[test]
+    fn presets_are_found_ignoring_case() {
+        assert!(preset("Krita").is_some());
+        assert!(preset("PHOTOSHOP").is_some());
+        assert!(preset("paint").is_none());
+    }
+}
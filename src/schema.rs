@@ -0,0 +1,156 @@
+use crate::chord::{key_names, modifier_names};
+use crate::configuration::{Config, Haptic, Key, KeyCombination};
+use schemars::gen::SchemaGenerator;
+use schemars::schema::{RootSchema, Schema};
+use schemars::{schema_for, JsonSchema};
+use serde_json::{json, Value};
+use std::fs::{read_to_string, write};
+use std::path::Path;
+
+/// Written next to a settings.json whose `$schema` is this file.
+pub const SCHEMA_FILE: &str = "settings.schema.json";
+
+pub fn config_schema() -> RootSchema {
+    schema_for!(Config)
+}
+
+/// Keeps the schema next to the config up to date when the config refers to it,
+/// so editors check it against the settings of this joyshop.
+pub fn write_schema(config_path: &Path, config: &Config) {
+    if config.schema.as_deref() != Some(SCHEMA_FILE) {
+        return;
+    }
+
+    let path = config_path.with_file_name(SCHEMA_FILE);
+    let schema = serde_json::to_string_pretty(&config_schema()).unwrap();
+    if read_to_string(&path).is_ok_and(|existing| existing == schema) {
+        return;
+    }
+    if let Err(e) = write(&path, schema) {
+        println!("couldn't write {} error: {}", path.display(), e);
+    }
+}
+
+fn from_json(value: Value) -> Schema {
+    serde_json::from_value(value).unwrap()
+}
+
+fn chord_description() -> String {
+    format!(
+        "Modifiers and then a key, joined by `+`, like `Ctrl+Shift+Z`. The modifiers are {}.",
+        modifier_names().join(", ")
+    )
+}
+
+impl JsonSchema for Key {
+    fn schema_name() -> String {
+        "Key".into()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        from_json(json!({
+            "type": "string",
+            "enum": key_names(),
+        }))
+    }
+}
+
+impl JsonSchema for KeyCombination {
+    fn schema_name() -> String {
+        "KeyCombination".into()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        let key = gen.subschema_for::<Key>();
+        let haptic = gen.subschema_for::<Option<Haptic>>();
+        let name = json!({
+            "type": "string",
+            "description": "Shown in the tooltip, the keys when not set.",
+        });
+        from_json(json!({
+            "anyOf": [
+                {
+                    "type": "string",
+                    "description": chord_description(),
+                    "examples": ["Ctrl+Z", "Ctrl+Shift+Z", "Alt+Space", "["],
+                },
+                {
+                    "type": "object",
+                    "properties": {
+                        "name": name,
+                        "keys": { "type": "string", "description": chord_description() },
+                        "haptic": haptic,
+                    },
+                    "required": ["keys"],
+                    "additionalProperties": false,
+                },
+                {
+                    "type": "object",
+                    "description": "The older way to write keys, use `keys` instead.",
+                    "properties": {
+                        "name": name,
+                        "key": key,
+                        "ctrl": { "type": "boolean" },
+                        "alt": { "type": "boolean" },
+                        "shift": { "type": "boolean" },
+                        "haptic": haptic,
+                    },
+                    "required": ["key"],
+                    "additionalProperties": false,
+                },
+            ],
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::create_default;
+
+    fn schema() -> Value {
+        serde_json::to_value(config_schema()).unwrap()
+    }
+
+    #[test]
+    fn every_default_setting_is_in_the_schema() {
+        let schema = schema();
+        let config = serde_json::to_value(create_default()).unwrap();
+        for key in config.as_object().unwrap().keys() {
+            assert!(schema["properties"].get(key).is_some(), "{}", key);
+        }
+        let profile = &schema["definitions"]["Profile"]["properties"];
+        for key in config["pro_controller"].as_object().unwrap().keys() {
+            assert!(profile.get(key).is_some(), "{}", key);
+        }
+    }
+
+    #[test]
+    fn keys_can_be_written_with_their_aliases() {
+        let keys = &schema()["definitions"]["Key"]["enum"];
+        for name in ["Z", "Escape", "Esc", "["].iter() {
+            assert!(keys.as_array().unwrap().contains(&json!(name)), "{}", name);
+        }
+    }
+
+    #[test]
+    fn the_schema_is_only_written_for_configs_that_refer_to_it() {
+        let dir = std::env::temp_dir().join(format!("joyshop-schema-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config_path = dir.join("settings.json");
+        let schema_path = dir.join(SCHEMA_FILE);
+
+        let mut config = create_default();
+        write_schema(&config_path, &config);
+        assert!(!schema_path.exists());
+
+        config.schema = Some(SCHEMA_FILE.into());
+        write_schema(&config_path, &config);
+        let written = read_to_string(&schema_path);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            written.unwrap(),
+            serde_json::to_string_pretty(&config_schema()).unwrap()
+        );
+    }
+}
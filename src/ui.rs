@@ -1,3 +1,5 @@
+use crate::configuration::KeyAction;
+use crate::events::{ControllerInfo, Event};
 use crate::window::{to_unicode, Window};
 use crossbeam_channel::Receiver;
 use std::mem::zeroed;
@@ -29,7 +31,7 @@ pub static WINDOW_STATE: Lazy<Mutex<WindowState>> = Lazy::new(|| {
     Mutex::new(state)
 });
 
-pub fn process_ui(rx: Receiver<String>, show_tooltip: bool) {
+pub fn process_ui(rx: Receiver<Event>, show_tooltip: bool) {
     unsafe {
         let win_class_name = "JoyShopWindowClass";
         let window = Window::create(win_class_name, "Joyshop", win_proc);
@@ -41,14 +43,14 @@ pub fn process_ui(rx: Receiver<String>, show_tooltip: bool) {
                     let mut point: POINT = zeroed();
                     GetCursorPos(&mut point);
 
-                    match rx.recv_timeout(Duration::from_millis(0)) {
-                        Ok(s) => {
+                    match rx.recv_timeout(Duration::from_millis(0)).map(tooltip) {
+                        Ok(Some(s)) => {
                             WINDOW_STATE.lock().unwrap().tool = s;
                             SetLayeredWindowAttributes(window_handle.into(), 0, 120, LWA_ALPHA);
                             InvalidateRect(window_handle.into(), null_mut(), TRUE);
                             last_point = point;
                         }
-                        Err(_) => {}
+                        _ => {}
                     }
 
                     if GetKeyState(VK_LBUTTON) < 0 {
@@ -87,6 +89,35 @@ pub fn process_ui(rx: Receiver<String>, show_tooltip: bool) {
     }
 }
 
+/// Text the tooltip shows for an event, if any.
+fn tooltip(event: Event) -> Option<String> {
+    let (text, controller) = match event {
+        Event::ActionFired {
+            action: KeyAction::KeyHold(k) | KeyAction::KeyClick(k),
+            controller,
+            ..
+        } => (k.name, controller),
+        Event::ProfileSwitched {
+            profile,
+            controller,
+        } => (profile, Some(controller)),
+        Event::BatteryReport { text, controller } => {
+            (format!("Battery {}", text), Some(controller))
+        }
+        Event::BatteryLow { level, .. } => return Some(format!("Battery {:?}", level)),
+        _ => return None,
+    };
+    Some(tooltip_text(text, controller.as_ref()))
+}
+
+/// The name of the controller goes after the text, when it has one.
+fn tooltip_text(text: String, controller: Option<&ControllerInfo>) -> String {
+    match controller.and_then(|controller| controller.name.as_ref()) {
+        Some(name) => format!("{} ({})", text, name),
+        None => text,
+    }
+}
+
 unsafe extern "system" fn win_proc(
     window_handle: HWND,
     message: UINT,
@@ -139,3 +170,57 @@ unsafe extern "system" fn win_proc(
         _ => DefWindowProcW(window_handle, message, wparam, lparam),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::BatteryLevel;
+    use crate::events::Source;
+
+    fn controller(name: Option<&str>) -> ControllerInfo {
+        ControllerInfo {
+            slot: 0,
+            name: name.map(String::from),
+        }
+    }
+
+    #[test]
+    fn key_bindings_and_profiles_show_with_the_controller_name() {
+        let action = serde_json::from_value::<KeyAction>(serde_json::json!({
+            "KeyClick": {"name": "Undo", "keys": "Ctrl+Z"}
+        }))
+        .unwrap();
+        let fired = Event::ActionFired {
+            controller: Some(controller(Some("Left hand"))),
+            source: Source::Remote,
+            action,
+        };
+        assert_eq!(tooltip(fired), Some("Undo (Left hand)".into()));
+
+        let switched = Event::ProfileSwitched {
+            controller: controller(None),
+            profile: "Krita".into(),
+        };
+        assert_eq!(tooltip(switched), Some("Krita".into()));
+
+        let low = Event::BatteryLow {
+            serial_number: "123".into(),
+            level: BatteryLevel::Critical,
+        };
+        assert_eq!(tooltip(low), Some("Battery Critical".into()));
+    }
+
+    #[test]
+    fn other_events_show_nothing() {
+        let fired = Event::ActionFired {
+            controller: None,
+            source: Source::Remote,
+            action: KeyAction::NextProfile,
+        };
+        assert_eq!(tooltip(fired), None);
+        let reloaded = Event::ConfigReloaded {
+            path: "settings.json".into(),
+        };
+        assert_eq!(tooltip(reloaded), None);
+    }
+}
@@ -0,0 +1,855 @@
+use crate::config_format::ConfigFormat;
+use crate::configuration::{
+    create_default, ControllerSettings, HapticSegment, Key, KeyAction, MidiCc, MidiCcStream,
+    MidiNote, MidiProgram, Mirror, OscMessage, OscStream, PlayerLight, PlayerLightMode,
+};
+use crate::extends::resolve_extends;
+use crate::input_recognizer::Side;
+use crate::migration::{migrate, CONFIG_VERSION};
+use crate::mirror::mirror_pairs;
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// A problem in the config file. Errors are replaced by defaults, warnings are kept as written.
+/// Missing settings aren't a problem, they are the defaults.
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Where in the file, like `profiles[1].zl`. Empty for the whole file.
+    pub path: String,
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(f, "{}", severity)?;
+        if let Some(line) = self.line {
+            write!(f, " (line {})", line)?;
+        }
+        if !self.path.is_empty() {
+            write!(f, " {}", self.path)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// Settings that are only written when they are set.
+const OPTIONAL_SETTINGS: [&str; 8] = [
+    "$schema",
+    "extends",
+    "start_profile",
+    "lights",
+    "mirror",
+    "http_port",
+    "osc_target",
+    "midi_port",
+];
+/// Settings of the whole config rather than of the base profile.
+const CONFIG_SETTINGS: [&str; 13] = [
+    "$schema",
+    "extends",
+    "version",
+    "show_tooltip",
+    "haptic_presets",
+    "player_lights",
+    "profiles",
+    "controllers",
+    "start_profile",
+    "gamepads",
+    "http_port",
+    "osc_target",
+    "midi_port",
+];
+/// Settings of a profile that aren't bindings.
+pub const PROFILE_SETTINGS: [&str; 4] = ["name", "lights", "pro_controller", "mirror"];
+
+/// Key and modifiers of a key binding, with the modifiers sorted.
+type Keys = (Key, Vec<i32>);
+
+/// Checks a config file, replacing what is broken with defaults.
+/// Returns the repaired config, which is `None` when the file can't be read as its format at all.
+/// With the `path` of the file, what it `extends` is merged in, otherwise `extends` is kept as it is.
+pub fn validate_config(
+    text: &str,
+    format: ConfigFormat,
+    path: Option<&Path>,
+) -> (Option<Value>, Vec<Diagnostic>) {
+    let mut value = match format.parse(text) {
+        Ok(value) => value,
+        Err((line, message)) => {
+            let diagnostic = Diagnostic {
+                severity: Severity::Error,
+                path: String::new(),
+                line,
+                message: format!("invalid {}: {}", format, message),
+            };
+            return (None, vec![diagnostic]);
+        }
+    };
+
+    let mut validator = Validator {
+        // Only JSON files are scanned for the lines of settings.
+        lines: match format {
+            ConfigFormat::Json => value_lines(text),
+            _ => BTreeMap::new(),
+        },
+        diagnostics: Vec::new(),
+    };
+    validator.check_version(&mut value);
+    if let Err(e) = migrate(&mut value) {
+        validator.report(Severity::Warning, "version", e);
+    }
+    if let Some(path) = path {
+        if let Err(e) = resolve_extends(&mut value, path) {
+            validator.report(Severity::Error, "extends", format!("{}, ignoring it", e));
+        }
+    }
+    validator.validate(&mut value);
+
+    let mut diagnostics = validator.diagnostics;
+    diagnostics.sort_by_key(|d| (d.line, d.path.clone()));
+    (Some(value), diagnostics)
+}
+
+struct Validator {
+    lines: BTreeMap<String, usize>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Validator {
+    fn report(&mut self, severity: Severity, path: &str, message: String) {
+        // Missing values have no line, so point at what contains them.
+        let mut at = path;
+        let line = loop {
+            if let Some(line) = self.lines.get(at) {
+                break Some(*line);
+            }
+            if at.is_empty() {
+                break None;
+            }
+            at = match at.rfind(['.', '[']) {
+                Some(end) => &at[..end],
+                None => "",
+            };
+        };
+
+        self.diagnostics.push(Diagnostic {
+            severity,
+            path: path.to_string(),
+            line,
+            message,
+        });
+    }
+
+    fn validate(&mut self, value: &mut Value) {
+        let default = serde_json::to_value(create_default()).unwrap();
+        let (config, default) = match (value.as_object_mut(), default) {
+            (Some(config), Value::Object(default)) => (config, default),
+            _ => {
+                self.report(Severity::Error, "", "settings must be an object".into());
+                *value = serde_json::to_value(create_default()).unwrap();
+                return;
+            }
+        };
+
+        for key in config.keys() {
+            if !default.contains_key(key) && !OPTIONAL_SETTINGS.contains(&key.as_str()) {
+                self.report(Severity::Warning, key, format!("unknown setting `{}`", key));
+            }
+        }
+
+        self.check_setting::<Option<String>>(config, &default, "$schema");
+        self.check_setting::<Option<String>>(config, &default, "extends");
+        self.check_setting::<bool>(config, &default, "show_tooltip");
+        self.check_setting::<BTreeMap<String, Vec<HapticSegment>>>(
+            config,
+            &default,
+            "haptic_presets",
+        );
+        self.check_setting::<PlayerLightMode>(config, &default, "player_lights");
+        self.check_setting::<Option<String>>(config, &default, "start_profile");
+        self.check_setting::<Vec<String>>(config, &default, "gamepads");
+        self.check_setting::<Option<u16>>(config, &default, "http_port");
+        self.check_setting::<Option<String>>(config, &default, "osc_target");
+        self.check_setting::<Option<String>>(config, &default, "midi_port");
+        if let Some(Value::String(target)) = config.get("osc_target") {
+            let target = target.clone();
+            self.check_osc_target("osc_target", &target);
+        }
+
+        let default_profile = default
+            .iter()
+            .filter(|(key, _)| !CONFIG_SETTINGS.contains(&key.as_str()))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect::<Map<_, _>>();
+
+        self.check_profile(config, &default_profile, "", true);
+        let mut profile_names = vec![config
+            .get("name")
+            .and_then(|name| name.as_str())
+            .unwrap_or("Default")
+            .to_string()];
+
+        if let Some(profiles) = self.check_array(config, "profiles") {
+            for (index, profile) in profiles.iter_mut().enumerate() {
+                let path = format!("profiles[{}]", index);
+                match profile.as_object_mut() {
+                    Some(profile) => {
+                        self.check_unknown_profile_settings(profile, &default_profile, &path);
+                        self.check_profile(profile, &default_profile, &path, false);
+                        let name = profile.get("name").and_then(|name| name.as_str());
+                        profile_names.push(name.unwrap_or("Default").to_string());
+                    }
+                    None => {
+                        self.report(Severity::Error, &path, "profile must be an object".into());
+                        *profile = Value::Object(Map::new());
+                        profile_names.push("Default".into());
+                    }
+                }
+            }
+        }
+
+        if let Some(controllers) = self.check_array(config, "controllers") {
+            let mut index = 0;
+            controllers.retain(|controller| {
+                let path = format!("controllers[{}]", index);
+                index += 1;
+                match serde_json::from_value::<ControllerSettings>(controller.clone()) {
+                    Ok(_) => true,
+                    Err(e) => {
+                        self.report(Severity::Error, &path, format!("{}, ignoring it", e));
+                        false
+                    }
+                }
+            });
+        }
+
+        self.check_profile_names(config, &profile_names);
+    }
+
+    /// Replaces a `version` that isn't one with the current version, before migrating.
+    fn check_version(&mut self, value: &mut Value) {
+        if let Some(config) = value.as_object_mut() {
+            let mut default = Map::new();
+            default.insert("version".into(), CONFIG_VERSION.into());
+            self.check_setting::<u32>(config, &default, "version");
+        }
+    }
+
+    /// Replaces a setting of the wrong type with its default.
+    fn check_setting<T: DeserializeOwned>(
+        &mut self,
+        object: &mut Map<String, Value>,
+        default: &Map<String, Value>,
+        key: &str,
+    ) {
+        let value = match object.get(key) {
+            Some(value) => value,
+            None => return,
+        };
+
+        if let Err(e) = serde_json::from_value::<T>(value.clone()) {
+            self.report(Severity::Error, key, format!("{}, using the default", e));
+            restore(object, default, key);
+        }
+    }
+
+    fn check_array<'a>(
+        &mut self,
+        object: &'a mut Map<String, Value>,
+        key: &str,
+    ) -> Option<&'a mut Vec<Value>> {
+        if object.get(key).is_some_and(|value| !value.is_array()) {
+            self.report(Severity::Error, key, format!("`{}` must be a list", key));
+            object.remove(key);
+        }
+        object.get_mut(key).and_then(|value| value.as_array_mut())
+    }
+
+    fn check_unknown_profile_settings(
+        &mut self,
+        profile: &Map<String, Value>,
+        default_profile: &Map<String, Value>,
+        path: &str,
+    ) {
+        for key in profile.keys() {
+            if !default_profile.contains_key(key) && !PROFILE_SETTINGS.contains(&key.as_str()) {
+                let path = format!("{}.{}", path, key);
+                self.report(
+                    Severity::Warning,
+                    &path,
+                    format!("unknown setting `{}`", key),
+                );
+            }
+        }
+    }
+
+    /// Broken bindings of the base profile are replaced with the defaults, the ones of other
+    /// profiles are removed, so they are the ones of the base profile.
+    fn check_profile(
+        &mut self,
+        profile: &mut Map<String, Value>,
+        default_profile: &Map<String, Value>,
+        path: &str,
+        is_base: bool,
+    ) {
+        let join = |key: &str| match path {
+            "" => key.to_string(),
+            _ => format!("{}.{}", path, key),
+        };
+
+        if let Some(name) = profile.get("name") {
+            if !name.is_string() {
+                self.report(
+                    Severity::Error,
+                    &join("name"),
+                    "name must be a string".into(),
+                );
+                profile.remove("name");
+            }
+        }
+        if let Some(lights) = profile.get("lights") {
+            if serde_json::from_value::<Option<[PlayerLight; 4]>>(lights.clone()).is_err() {
+                let message = "lights must be 4 of `Off`, `On` or `Flash`".into();
+                self.report(Severity::Error, &join("lights"), message);
+                profile.remove("lights");
+            }
+        }
+        if let Some(mirror) = profile.get("mirror") {
+            let checked = serde_json::from_value::<Mirror>(mirror.clone())
+                .map_err(|_| "mirror must be `LeftToRight`, `RightToLeft` or `Swap`, or an object with `mode` and `buttons`".to_string())
+                .and_then(|mirror| mirror_pairs(&mirror));
+            if let Err(e) = checked {
+                self.report(
+                    Severity::Error,
+                    &join("mirror"),
+                    format!("{}, ignoring it", e),
+                );
+                profile.remove("mirror");
+            }
+        }
+
+        let bindings = default_profile
+            .keys()
+            .filter(|key| !PROFILE_SETTINGS.contains(&key.as_str()))
+            .cloned()
+            .collect::<Vec<_>>();
+        let no_defaults = Map::new();
+        let defaults = if is_base {
+            default_profile
+        } else {
+            &no_defaults
+        };
+        self.check_bindings(profile, defaults, &bindings, path, joycon_side);
+
+        match profile.get_mut("pro_controller") {
+            Some(Value::Object(pro_controller)) => {
+                let default = default_profile["pro_controller"].as_object().unwrap();
+                let bindings = default.keys().cloned().collect::<Vec<_>>();
+                let defaults = if is_base { default } else { &no_defaults };
+                let path = join("pro_controller");
+                self.check_bindings(pro_controller, defaults, &bindings, &path, joycon_side);
+            }
+            Some(_) => {
+                let message = if is_base {
+                    "pro_controller must be an object, using the default"
+                } else {
+                    "pro_controller must be an object, using the one of the base profile"
+                };
+                self.report(Severity::Error, &join("pro_controller"), message.into());
+                profile.remove("pro_controller");
+            }
+            None => {}
+        }
+    }
+
+    fn check_bindings(
+        &mut self,
+        object: &mut Map<String, Value>,
+        default: &Map<String, Value>,
+        bindings: &[String],
+        path: &str,
+        device: fn(&str) -> Side,
+    ) {
+        let join = |key: &str| match path {
+            "" => key.to_string(),
+            _ => format!("{}.{}", path, key),
+        };
+
+        let mut combinations: Vec<(String, Side, Keys)> = Vec::new();
+        for binding in bindings {
+            let binding_path = join(binding);
+            let value = match object.get(binding) {
+                Some(value) => value,
+                None => continue,
+            };
+
+            let action = match serde_json::from_value::<KeyAction>(value.clone()) {
+                Ok(action) => action,
+                Err(e) => {
+                    let message = if default.contains_key(binding) {
+                        format!("{}, using the default", e)
+                    } else {
+                        format!("{}, using the one of the base profile", e)
+                    };
+                    self.report(Severity::Error, &binding_path, message);
+                    restore(object, default, binding);
+                    continue;
+                }
+            };
+
+            let (k, is_click) = match &action {
+                KeyAction::KeyClick(k) => (k, true),
+                KeyAction::KeyHold(k) => (k, false),
+                KeyAction::Osc(OscMessage {
+                    address, target, ..
+                })
+                | KeyAction::OscStream(OscStream {
+                    address, target, ..
+                }) => {
+                    if !address.starts_with('/') {
+                        let message = format!("the OSC address `{}` must start with `/`", address);
+                        self.report(Severity::Warning, &binding_path, message);
+                    }
+                    if let Some(target) = target {
+                        self.check_osc_target(&binding_path, target);
+                    }
+                    continue;
+                }
+                KeyAction::MidiNote(MidiNote {
+                    note,
+                    velocity,
+                    channel,
+                }) => {
+                    self.check_midi(&binding_path, &[*note, *velocity], *channel);
+                    continue;
+                }
+                KeyAction::MidiCc(MidiCc {
+                    controller,
+                    value,
+                    release_value,
+                    channel,
+                }) => {
+                    let values = [*controller, *value, release_value.unwrap_or(0)];
+                    self.check_midi(&binding_path, &values, *channel);
+                    continue;
+                }
+                KeyAction::MidiProgram(MidiProgram { program, channel }) => {
+                    self.check_midi(&binding_path, &[*program], *channel);
+                    continue;
+                }
+                KeyAction::MidiCcStream(MidiCcStream {
+                    controllers,
+                    channel,
+                    ..
+                }) => {
+                    self.check_midi(&binding_path, controllers, *channel);
+                    continue;
+                }
+                _ => continue,
+            };
+
+            if is_click && k.key == Key::None {
+                let message = if !k.modifiers.is_empty() {
+                    "a click of modifiers only does nothing, use KeyHold to hold them"
+                } else {
+                    "a click without keys does nothing"
+                };
+                self.report(Severity::Warning, &binding_path, message.into());
+            }
+
+            // The same keys on two buttons of one device are likely a copy-paste mistake.
+            let side = device(binding);
+            if k.key == Key::None && k.modifiers.is_empty() {
+                continue;
+            }
+            let mut modifiers = k.modifiers.iter().map(|m| *m as i32).collect::<Vec<_>>();
+            modifiers.sort_unstable();
+            let combination = (k.key, modifiers);
+            match combinations
+                .iter()
+                .find(|(_, s, c)| *s == side && *c == combination)
+            {
+                Some((other, _, _)) => {
+                    let message = format!("same keys as `{}`", other);
+                    self.report(Severity::Warning, &binding_path, message);
+                }
+                None => combinations.push((binding.clone(), side, combination)),
+            }
+        }
+    }
+
+    fn check_osc_target(&mut self, path: &str, target: &str) {
+        let is_valid = target
+            .rsplit_once(':')
+            .is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok());
+        if !is_valid {
+            let message = format!("`{}` isn't an OSC target like `127.0.0.1:9000`", target);
+            self.report(Severity::Warning, path, message);
+        }
+    }
+
+    fn check_midi(&mut self, path: &str, values: &[u8], channel: u8) {
+        if let Some(value) = values.iter().find(|&&value| value > 127) {
+            let message = format!(
+                "MIDI values are 0 to 127, {} is sent as {}",
+                value,
+                value & 0x7F
+            );
+            self.report(Severity::Warning, path, message);
+        }
+        if !(1..=16).contains(&channel) {
+            let message = format!(
+                "MIDI channels are 1 to 16, {} is sent on {}",
+                channel,
+                channel.clamp(1, 16)
+            );
+            self.report(Severity::Warning, path, message);
+        }
+    }
+
+    /// Reports switches to unknown profiles, and profiles no binding switches to.
+    fn check_profile_names(&mut self, config: &Map<String, Value>, names: &[String]) {
+        let index_of = |name: &str| names.iter().position(|n| n == name);
+
+        let profiles = std::iter::once(config)
+            .chain(
+                config
+                    .get("profiles")
+                    .and_then(|profiles| profiles.as_array())
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|profile| profile.as_object()),
+            )
+            .collect::<Vec<_>>();
+
+        let mut seen = BTreeSet::new();
+        for (index, name) in names.iter().enumerate() {
+            if !seen.insert(name) {
+                let path = format!("profiles[{}].name", index - 1);
+                let message = format!("another profile is named `{}` too", name);
+                self.report(Severity::Warning, &path, message);
+            }
+        }
+
+        let mut starts = vec![0];
+        let start_profile = config
+            .get("start_profile")
+            .and_then(|name| name.as_str())
+            .map(|name| ("start_profile".to_string(), name));
+        let controller_profiles = config
+            .get("controllers")
+            .and_then(|controllers| controllers.as_array())
+            .into_iter()
+            .flatten()
+            .enumerate()
+            .filter_map(|(index, controller)| {
+                let name = controller.get("profile")?.as_str()?;
+                Some((format!("controllers[{}].profile", index), name))
+            });
+        for (path, name) in start_profile.into_iter().chain(controller_profiles) {
+            match index_of(name) {
+                Some(index) => starts.push(index),
+                None => {
+                    let message = format!("unknown profile `{}`", name);
+                    self.report(Severity::Warning, &path, message);
+                }
+            }
+        }
+
+        let mut switches = vec![Vec::new(); profiles.len()];
+        for (index, profile) in profiles.iter().enumerate() {
+            let path = match index {
+                0 => String::new(),
+                _ => format!("profiles[{}]", index - 1),
+            };
+            for (key, action) in profile_actions(profile) {
+                match action {
+                    KeyAction::NextProfile => switches[index].push((index + 1) % profiles.len()),
+                    KeyAction::SwitchProfile(name) => match index_of(&name) {
+                        Some(target) => switches[index].push(target),
+                        None => {
+                            let path = match path.as_str() {
+                                "" => key,
+                                _ => format!("{}.{}", path, key),
+                            };
+                            let message = format!("unknown profile `{}`", name);
+                            self.report(Severity::Warning, &path, message);
+                        }
+                    },
+                    _ => {}
+                }
+            }
+        }
+
+        let mut reachable = BTreeSet::new();
+        while let Some(index) = starts.pop() {
+            if reachable.insert(index) {
+                starts.extend(switches[index].iter().copied());
+            }
+        }
+        for (index, name) in names.iter().enumerate().skip(1) {
+            if !reachable.contains(&index) {
+                let path = format!("profiles[{}]", index - 1);
+                let message = format!(
+                    "no binding switches to profile `{}`, only the control socket can",
+                    name
+                );
+                self.report(Severity::Warning, &path, message);
+            }
+        }
+    }
+}
+
+/// The JoyCon a binding belongs to.
+fn joycon_side(binding: &str) -> Side {
+    const LEFT: [&str; 8] = ["zl", "l", "minus", "up", "down", "left", "right", "capture"];
+    if LEFT.contains(&binding) || binding.starts_with("left_") {
+        Side::Left
+    } else {
+        Side::Right
+    }
+}
+
+fn restore(object: &mut Map<String, Value>, default: &Map<String, Value>, key: &str) {
+    match default.get(key) {
+        Some(value) => object.insert(key.to_string(), value.clone()),
+        None => object.remove(key),
+    };
+}
+
+/// Bindings of a profile, with the ones of its Pro Controller table prefixed.
+fn profile_actions(profile: &Map<String, Value>) -> Vec<(String, KeyAction)> {
+    let actions = |object: &Map<String, Value>, prefix: &str| {
+        object
+            .iter()
+            .filter_map(|(key, value)| {
+                let action = serde_json::from_value::<KeyAction>(value.clone()).ok()?;
+                Some((format!("{}{}", prefix, key), action))
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let mut result = actions(profile, "");
+    if let Some(pro_controller) = profile.get("pro_controller").and_then(|v| v.as_object()) {
+        result.extend(actions(pro_controller, "pro_controller."));
+    }
+    result
+}
+
+/// Line of every value in a JSON text, by the path `Validator` reports it under.
+/// Object members are at the line of their key.
+fn value_lines(json: &str) -> BTreeMap<String, usize> {
+    let mut scanner = Scanner {
+        bytes: json.as_bytes(),
+        position: 0,
+        line: 1,
+        lines: BTreeMap::new(),
+    };
+    scanner.value(String::new());
+    scanner.lines
+}
+
+struct Scanner<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    line: usize,
+    lines: BTreeMap<String, usize>,
+}
+
+impl<'a> Scanner<'a> {
+    fn peek(&mut self) -> Option<u8> {
+        while let Some(&byte) = self.bytes.get(self.position) {
+            match byte {
+                b'\n' => self.line += 1,
+                b' ' | b'\t' | b'\r' => {}
+                _ => return Some(byte),
+            }
+            self.position += 1;
+        }
+        None
+    }
+
+    fn value(&mut self, path: String) {
+        match self.peek() {
+            Some(b'{') => {
+                self.position += 1;
+                while let Some(byte) = self.peek() {
+                    match byte {
+                        b'}' => {
+                            self.position += 1;
+                            return;
+                        }
+                        b',' => self.position += 1,
+                        b'"' => {
+                            let line = self.line;
+                            let key = self.string();
+                            let path = match path.as_str() {
+                                "" => key,
+                                _ => format!("{}.{}", path, key),
+                            };
+                            self.lines.insert(path.clone(), line);
+                            if self.peek() == Some(b':') {
+                                self.position += 1;
+                            }
+                            self.value(path);
+                        }
+                        _ => return,
+                    }
+                }
+            }
+            Some(b'[') => {
+                self.position += 1;
+                let mut index = 0;
+                while let Some(byte) = self.peek() {
+                    match byte {
+                        b']' => {
+                            self.position += 1;
+                            return;
+                        }
+                        b',' => self.position += 1,
+                        _ => {
+                            let path = format!("{}[{}]", path, index);
+                            self.lines.insert(path.clone(), self.line);
+                            self.value(path);
+                            index += 1;
+                        }
+                    }
+                }
+            }
+            Some(b'"') => {
+                self.string();
+            }
+            Some(_) => {
+                while let Some(&byte) = self.bytes.get(self.position) {
+                    if b",]} \t\r\n".contains(&byte) {
+                        break;
+                    }
+                    self.position += 1;
+                }
+            }
+            None => {}
+        }
+    }
+
+    fn string(&mut self) -> String {
+        let start = self.position;
+        self.position += 1;
+        while let Some(&byte) = self.bytes.get(self.position) {
+            self.position += 1;
+            match byte {
+                b'\\' => self.position += 1,
+                b'"' => break,
+                _ => {}
+            }
+        }
+        let end = self.position.min(self.bytes.len());
+        serde_json::from_slice(&self.bytes[start..end]).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::Config;
+
+    fn check(json: &str) -> (Value, Vec<Diagnostic>) {
+        let (value, diagnostics) = validate_config(json, ConfigFormat::Json, None);
+        (value.unwrap(), diagnostics)
+    }
+
+    fn messages(diagnostics: &[Diagnostic]) -> Vec<String> {
+        diagnostics.iter().map(|d| d.to_string()).collect()
+    }
+
+    #[test]
+    fn lines_are_found_for_nested_values() {
+        let json = "{\n  \"a\": 1,\n  \"b\": {\n    \"c\": \"}\\\"{\",\n    \"d\": [\n      2,\n      {\"e\": 3}\n    ]\n  }\n}";
+        let lines = value_lines(json);
+        assert_eq!(lines.get(""), None);
+        assert_eq!(lines["a"], 2);
+        assert_eq!(lines["b"], 3);
+        assert_eq!(lines["b.c"], 4);
+        assert_eq!(lines["b.d"], 5);
+        assert_eq!(lines["b.d[0]"], 6);
+        assert_eq!(lines["b.d[1].e"], 7);
+    }
+
+    #[test]
+    fn a_broken_version_is_replaced_and_the_rest_is_kept() {
+        let (value, diagnostics) = check("{\n  \"version\": \"1\",\n  \"show_tooltip\": false\n}");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+        assert_eq!(diagnostics[0].path, "version");
+        assert_eq!(diagnostics[0].line, Some(2));
+
+        let config = serde_json::from_value::<Config>(value).unwrap();
+        assert_eq!(config.version, CONFIG_VERSION);
+        assert!(!config.show_tooltip);
+    }
+
+    #[test]
+    fn broken_bindings_are_replaced_with_the_default() {
+        let (value, diagnostics) = check("{\n  \"version\": 1,\n  \"a\": \"Broken\"\n}");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+        assert_eq!(diagnostics[0].path, "a");
+        assert_eq!(diagnostics[0].line, Some(3));
+        assert_eq!(
+            value["a"],
+            serde_json::to_value(create_default()).unwrap()["a"]
+        );
+    }
+
+    #[test]
+    fn same_keys_are_only_reported_on_one_joycon() {
+        let (_, diagnostics) = check(
+            r#"{
+  "version": 1,
+  "zl": { "KeyClick": { "keys": "Ctrl+Z" } },
+  "plus": { "KeyClick": { "keys": "Ctrl+Z" } },
+  "pro_controller": {
+    "zl": { "KeyClick": { "keys": "B" } },
+    "zr": { "KeyClick": { "keys": "B" } },
+    "l": { "KeyClick": { "keys": "B" } }
+  }
+}"#,
+        );
+        assert_eq!(
+            messages(&diagnostics),
+            ["warning (line 8) pro_controller.l: same keys as `zl`"]
+        );
+    }
+
+    #[test]
+    fn profiles_without_a_switch_mention_the_control_socket() {
+        let (_, diagnostics) = check(
+            r#"{
+  "version": 1,
+  "plus": { "SwitchProfile": "Reached" },
+  "profiles": [
+    { "name": "Reached" },
+    { "name": "Other" }
+  ]
+}"#,
+        );
+        assert_eq!(
+            messages(&diagnostics),
+            ["warning (line 6) profiles[1]: no binding switches to profile `Other`, only the control socket can"]
+        );
+    }
+}